@@ -0,0 +1,295 @@
+use std::ops::{Index, IndexMut};
+
+use bevy::utils::HashMap;
+
+use super::coord::Coord;
+use super::field::{iter_coords, to_1d, to_coord, Field, FieldSize};
+use super::hex::Hex;
+
+/// The width & height of a chunk of a [ChunkedField], in cells.
+pub const CHUNK_SIZE: usize = 16;
+
+/// A sparse 2D field of values, allocated in chunks of `CHUNK_SIZE * CHUNK_SIZE` cells the first
+/// time a cell of the chunk is written. Coordinates are unbounded & may be negative.
+///
+/// Cells of chunks that haven't been allocated read as the default value. Iteration only visits
+/// allocated chunks, in no particular order. Layers that are mostly empty, like the occupancy of
+/// a [Congestion](crate::pathfinding::Congestion) layer, only allocate the chunks in use.
+#[derive(Default, Clone, Debug)]
+pub struct ChunkedField<T: Default> {
+    chunks: HashMap<Coord, Field<T>>,
+    empty: T,
+}
+
+impl<T: Default> ChunkedField<T> {
+    /// Creates a new field without any chunks.
+    pub fn new() -> Self {
+        Self {
+            chunks: HashMap::default(),
+            empty: T::default(),
+        }
+    }
+
+    /// Returns the coordinate of the chunk containing a coordinate.
+    pub fn to_chunk(coord: &Coord) -> Coord {
+        let size = CHUNK_SIZE as i32;
+        Coord::new(coord.x.div_euclid(size), coord.y.div_euclid(size))
+    }
+
+    /// Returns the coordinate of a cell within its chunk.
+    pub fn to_local(coord: &Coord) -> Coord {
+        let size = CHUNK_SIZE as i32;
+        Coord::new(coord.x.rem_euclid(size), coord.y.rem_euclid(size))
+    }
+
+    /// Returns the 1-dimensional index of a coordinate within the data of its chunk.
+    pub fn to_1d(coord: &Coord) -> usize {
+        to_1d(&Self::to_local(coord), CHUNK_SIZE)
+    }
+
+    /// Returns the coordinate of the cell at a 1-dimensional index within the given chunk.
+    pub fn to_coord(chunk: &Coord, index: usize) -> Coord {
+        *chunk * CHUNK_SIZE as i32 + to_coord(index, CHUNK_SIZE)
+    }
+
+    /// Returns the width & height spanned by the allocated chunks, see [ChunkedField::bounds].
+    pub fn size(&self) -> FieldSize {
+        match self.bounds() {
+            Some((min, max)) => FieldSize {
+                width: (max.x - min.x + 1) as usize,
+                height: (max.y - min.y + 1) as usize,
+            },
+            None => FieldSize::default(),
+        }
+    }
+
+    /// Returns true if the chunk containing the given coordinate is allocated.
+    pub fn within_bounds(&self, coord: &Coord) -> bool {
+        self.chunks.contains_key(&Self::to_chunk(coord))
+    }
+
+    /// Returns the value of a cell, `None` if its chunk isn't allocated.
+    pub fn get(&self, coord: &Coord) -> Option<&T> {
+        self.chunks
+            .get(&Self::to_chunk(coord))
+            .map(|chunk| &chunk[&Self::to_local(coord)])
+    }
+
+    /// Returns the value of a cell, allocating its chunk if needed.
+    pub fn get_mut(&mut self, coord: &Coord) -> &mut T {
+        let chunk = self.chunks.entry(Self::to_chunk(coord)).or_insert_with(|| {
+            let data = (0..CHUNK_SIZE * CHUNK_SIZE).map(|_| T::default()).collect();
+            Field::new(CHUNK_SIZE, CHUNK_SIZE, data)
+        });
+        &mut chunk[&Self::to_local(coord)]
+    }
+
+    /// Returns the number of allocated chunks.
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Iterates over the coordinates of the allocated chunks.
+    pub fn iter_chunks(&self) -> impl Iterator<Item = Coord> + '_ {
+        self.chunks.keys().copied()
+    }
+
+    /// Frees the chunk with the given chunk coordinate, returns its values if it was allocated.
+    pub fn remove_chunk(&mut self, chunk: &Coord) -> Option<Field<T>> {
+        self.chunks.remove(chunk)
+    }
+
+    /// Returns the smallest & largest coordinate of the allocated chunks, `None` without chunks.
+    pub fn bounds(&self) -> Option<(Coord, Coord)> {
+        let size = CHUNK_SIZE as i32;
+        let min_x = self.chunks.keys().map(|chunk| chunk.x).min()?;
+        let min_y = self.chunks.keys().map(|chunk| chunk.y).min()?;
+        let max_x = self.chunks.keys().map(|chunk| chunk.x).max()?;
+        let max_y = self.chunks.keys().map(|chunk| chunk.y).max()?;
+        Some((
+            Coord::new(min_x * size, min_y * size),
+            Coord::new((max_x + 1) * size - 1, (max_y + 1) * size - 1),
+        ))
+    }
+
+    /// Returns the 4-directional neighbors of a coordinate within allocated chunks.
+    pub fn neighbors<'a>(&'a self, coord: &'a Coord) -> impl Iterator<Item = Coord> + 'a {
+        coord.neighbors().filter(|c| self.within_bounds(c))
+    }
+
+    /// Returns the 8-directional neighbors of a coordinate within allocated chunks.
+    pub fn neighbors8<'a>(&'a self, coord: &'a Coord) -> impl Iterator<Item = Coord> + 'a {
+        coord.neighbors8().filter(|c| self.within_bounds(c))
+    }
+
+    /// Returns the hex neighbors of a coordinate within allocated chunks, for fields of hexes
+    /// stored at their offset coordinates.
+    pub fn neighbors_hex<'a>(&'a self, coord: &'a Coord) -> impl Iterator<Item = Coord> + 'a {
+        Hex::from_offset(*coord)
+            .neighbors()
+            .map(Hex::to_offset)
+            .filter(|c| self.within_bounds(c))
+    }
+
+    /// Iterates over the items of the allocated chunks, in the same order as `iter_coords`.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.chunks.values().flat_map(|chunk| chunk.iter())
+    }
+
+    /// Iterates over the items of the allocated chunks, in the same order as `iter_coords`.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.chunks.values_mut().flat_map(|chunk| chunk.iter_mut())
+    }
+
+    /// Iterates over the coordinates of the allocated chunks.
+    pub fn iter_coords(&self) -> impl Iterator<Item = Coord> + '_ {
+        let size = CHUNK_SIZE as i32;
+        self.chunks.iter().flat_map(move |(chunk, field)| {
+            field
+                .iter_coords()
+                .map(move |local| Coord::new(chunk.x * size, chunk.y * size) + local)
+        })
+    }
+
+    /// Resizes the field to `width * height` cells from the origin, keeping every value within the
+    /// new dimensions at its coordinate. Chunks outside of them are freed.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        let (width, height) = (width as i32, height as i32);
+        let size = CHUNK_SIZE as i32;
+        self.chunks.retain(|chunk, _| {
+            chunk.x >= 0 && chunk.y >= 0 && chunk.x * size < width && chunk.y * size < height
+        });
+        for (chunk, field) in self.chunks.iter_mut() {
+            for local in iter_coords(CHUNK_SIZE, CHUNK_SIZE) {
+                let coord = *chunk * size + local;
+                if coord.x >= width || coord.y >= height {
+                    field[&local] = T::default();
+                }
+            }
+        }
+    }
+
+    /// Clears the field, keeping the allocated chunks.
+    pub fn clear(&mut self) {
+        for chunk in self.chunks.values_mut() {
+            chunk.clear();
+        }
+    }
+}
+
+impl<T: Default> Index<&Coord> for ChunkedField<T> {
+    type Output = T;
+    fn index<'a>(&'a self, coord: &Coord) -> &'a T {
+        self.get(coord).unwrap_or(&self.empty)
+    }
+}
+
+impl<T: Default> IndexMut<&Coord> for ChunkedField<T> {
+    fn index_mut<'a>(&'a mut self, coord: &Coord) -> &'a mut T {
+        self.get_mut(coord)
+    }
+}
+
+impl<T: Default> Index<&Hex> for ChunkedField<T> {
+    type Output = T;
+    fn index<'a>(&'a self, hex: &Hex) -> &'a T {
+        &self[&hex.to_offset()]
+    }
+}
+
+impl<T: Default> IndexMut<&Hex> for ChunkedField<T> {
+    fn index_mut<'a>(&'a mut self, hex: &Hex) -> &'a mut T {
+        &mut self[&hex.to_offset()]
+    }
+}
+
+impl<T: Default + Clone> From<&Field<T>> for ChunkedField<T> {
+    fn from(field: &Field<T>) -> Self {
+        let mut chunked = Self::new();
+        for (coord, value) in field.iter_coords().zip(field.iter()) {
+            chunked[&coord] = value.clone();
+        }
+        chunked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocates_chunks_on_write() {
+        let mut field: ChunkedField<u8> = ChunkedField::new();
+        assert_eq!(field[&Coord::new(-100, 5)], 0);
+        assert_eq!(field.chunk_count(), 0);
+        assert_eq!(field.size(), FieldSize::default());
+
+        field[&Coord::new(-1, -1)] = 3;
+        field[&Coord::new(16, 0)] = 4;
+        field[&Hex::new(2, 3)] = 5;
+        assert_eq!(field[&Coord::new(-1, -1)], 3);
+        assert_eq!(field[&Coord::new(16, 0)], 4);
+        assert_eq!(field[&Hex::new(2, 3).to_offset()], 5);
+        assert_eq!(field.chunk_count(), 3);
+        assert!(field.within_bounds(&Coord::new(-16, -16)));
+        assert!(!field.within_bounds(&Coord::new(-17, 0)));
+        assert_eq!(
+            field.bounds(),
+            Some((Coord::new(-16, -16), Coord::new(31, 15)))
+        );
+        assert_eq!(
+            field.size(),
+            FieldSize {
+                width: 48,
+                height: 32
+            }
+        );
+
+        let coord = Coord::new(-1, -1);
+        let index = ChunkedField::<u8>::to_1d(&coord);
+        let chunk = ChunkedField::<u8>::to_chunk(&coord);
+        assert_eq!(ChunkedField::<u8>::to_coord(&chunk, index), coord);
+    }
+
+    #[test]
+    fn iterates_allocated_chunks() {
+        let mut field: ChunkedField<u8> = ChunkedField::new();
+        field[&Coord::new(-1, -1)] = 3;
+        field[&Coord::new(16, 0)] = 4;
+        assert_eq!(field.iter_coords().count(), 2 * CHUNK_SIZE * CHUNK_SIZE);
+
+        let values: Vec<(Coord, u8)> = field
+            .iter_coords()
+            .zip(field.iter().copied())
+            .filter(|(_, value)| *value != 0)
+            .collect();
+        assert_eq!(values.len(), 2);
+        for (coord, value) in values {
+            assert_eq!(field[&coord], value);
+        }
+
+        assert_eq!(field.neighbors8(&Coord::new(-1, -1)).count(), 3);
+        assert_eq!(field.neighbors(&Coord::new(-16, -16)).count(), 2);
+
+        let dense = Field::new(3, 2, vec![1u8, 2, 3, 4, 5, 6]);
+        let chunked = ChunkedField::from(&dense);
+        for coord in dense.iter_coords() {
+            assert_eq!(chunked[&coord], dense[&coord]);
+        }
+    }
+
+    #[test]
+    fn resize_keeps_coords() {
+        let mut field: ChunkedField<u8> = ChunkedField::new();
+        field[&Coord::new(-1, 0)] = 1;
+        field[&Coord::new(2, 3)] = 2;
+        field[&Coord::new(10, 3)] = 3;
+        field[&Coord::new(20, 3)] = 4;
+        field.resize(8, 8);
+        assert_eq!(field.chunk_count(), 1);
+        assert_eq!(field[&Coord::new(-1, 0)], 0);
+        assert_eq!(field[&Coord::new(2, 3)], 2);
+        assert_eq!(field[&Coord::new(10, 3)], 0);
+        assert_eq!(field[&Coord::new(20, 3)], 0);
+    }
+}
@@ -1,8 +1,9 @@
 use std::ops::{Index, IndexMut};
 
 use super::coord::{neighbors, neighbors8, Coord};
+use super::hex::{hex_neighbors, Hex};
 
-#[derive(Default, Clone, Debug)]
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
 pub struct FieldSize {
     pub width: usize,
     pub height: usize,
@@ -52,6 +53,12 @@ impl<T: Default> Field<T> {
         neighbors8(coord, self.size.width, self.size.height)
     }
 
+    /// Returns the hex neighbors of a coordinate, for fields of hexes stored at their offset
+    /// coordinates.
+    pub fn neighbors_hex<'a>(&'a self, coord: &'a Coord) -> impl Iterator<Item = Coord> + 'a {
+        hex_neighbors(coord, self.size.width, self.size.height)
+    }
+
     /// Iterates over the items of the field.
     pub fn iter(&self) -> impl Iterator<Item = &T> {
         self.data.iter()
@@ -67,11 +74,18 @@ impl<T: Default> Field<T> {
         iter_coords(self.size.width, self.size.height)
     }
 
-    /// Resize the field.
+    /// Resize the field, keeping every value within the new dimensions at its coordinate.
     pub fn resize(&mut self, width: usize, height: usize) {
-        self.data.resize_with(width * height, || T::default());
-        self.size.width = width;
-        self.size.height = height;
+        let mut data: Vec<T> = (0..width * height).map(|_| T::default()).collect();
+        let old_width = self.size.width;
+        for (index, value) in self.data.drain(..).enumerate() {
+            let coord = to_coord(index, old_width);
+            if coord.x < width as i32 && coord.y < height as i32 {
+                data[to_1d(&coord, width)] = value;
+            }
+        }
+        self.data = data;
+        self.size = FieldSize { width, height };
     }
 
     /// Clears the field.
@@ -96,6 +110,19 @@ impl<T: Default> IndexMut<&Coord> for Field<T> {
     }
 }
 
+impl<T: Default> Index<&Hex> for Field<T> {
+    type Output = T;
+    fn index<'a>(&'a self, hex: &Hex) -> &'a T {
+        &self[&hex.to_offset()]
+    }
+}
+
+impl<T: Default> IndexMut<&Hex> for Field<T> {
+    fn index_mut<'a>(&'a mut self, hex: &Hex) -> &'a mut T {
+        &mut self[&hex.to_offset()]
+    }
+}
+
 /// Returns the 1-dimensional index of a coordinate.
 #[inline]
 pub fn to_1d(coord: &Coord, width: usize) -> usize {
@@ -115,3 +142,34 @@ pub fn to_coord(i: usize, width: usize) -> Coord {
 pub fn iter_coords(width: usize, height: usize) -> impl Iterator<Item = Coord> {
     (0..width * height).map(move |i| to_coord(i, width))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resize_keeps_coords() {
+        let old = Field::new(3, 2, vec![1u8, 2, 3, 4, 5, 6]);
+
+        // Growing keeps every value at its coordinate, new cells get the default value.
+        let mut field = old.clone();
+        field.resize(4, 3);
+        assert_eq!((field.size.width, field.size.height), (4, 3));
+        for coord in field.iter_coords() {
+            let expected = if old.within_bounds(&coord) {
+                old[&coord]
+            } else {
+                0
+            };
+            assert_eq!(field[&coord], expected, "{:?}", coord);
+        }
+
+        // Shrinking keeps the values still within bounds at their coordinate.
+        let mut field = old.clone();
+        field.resize(2, 1);
+        assert_eq!((field.size.width, field.size.height), (2, 1));
+        for coord in field.iter_coords() {
+            assert_eq!(field[&coord], old[&coord], "{:?}", coord);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use std::ops::{Add, Mul, Sub};
+
+use crate::prelude::*;
+
+/// The 6 neighbor directions of a hex, counter-clockwise starting east.
+pub const HEX_NEIGHBORS: [Hex; 6] = [
+    Hex { q: 1, r: 0 },
+    Hex { q: 1, r: -1 },
+    Hex { q: 0, r: -1 },
+    Hex { q: -1, r: 0 },
+    Hex { q: -1, r: 1 },
+    Hex { q: 0, r: 1 },
+];
+
+/// √3 / 2, the distance between hex rows relative to the distance between hex centers.
+const ROW_SPACING: f32 = 0.866_025_4;
+
+/// A coordinate in a grid of pointy-top hexagons, in axial coordinates. The third cube
+/// coordinate is `s = -q - r`.
+///
+/// Hex grids store their cells in a [Field] at the "odd-r" offset [Coord] of the hex, where
+/// odd rows are shifted half a cell to the right, see [Hex::to_offset].
+#[cfg_attr(feature = "dev", derive(bevy_inspector_egui::Inspectable))]
+#[derive(Component, Default, Debug, PartialEq, Eq, Hash, Copy, Clone, PartialOrd, Ord)]
+pub struct Hex {
+    pub q: i32,
+    pub r: i32,
+}
+
+impl Hex {
+    /// Creates a new hex.
+    pub fn new(q: i32, r: i32) -> Self {
+        Self { q, r }
+    }
+
+    /// Returns the third cube coordinate.
+    pub fn s(&self) -> i32 {
+        -self.q - self.r
+    }
+
+    /// Returns the 6 neighbors of a hex.
+    pub fn neighbors(self) -> impl Iterator<Item = Hex> {
+        HEX_NEIGHBORS.iter().map(move |&dir| self + dir)
+    }
+
+    /// Returns the number of steps between two hexes.
+    pub fn distance(&self, other: Hex) -> u16 {
+        let diff = *self - other;
+        ((diff.q.abs() + diff.r.abs() + diff.s().abs()) / 2) as u16
+    }
+
+    /// Returns the hexes at exactly `radius` steps, the hex itself for a radius of 0.
+    pub fn ring(self, radius: u32) -> impl Iterator<Item = Hex> {
+        if radius == 0 {
+            return vec![self].into_iter();
+        }
+
+        // Walk the 6 sides, turning at every corner.
+        let mut hex = self + HEX_NEIGHBORS[4] * radius as i32;
+        let mut ring = Vec::with_capacity(6 * radius as usize);
+        for dir in HEX_NEIGHBORS {
+            for _ in 0..radius {
+                ring.push(hex);
+                hex = hex + dir;
+            }
+        }
+        ring.into_iter()
+    }
+
+    /// Returns the hexes within `radius` steps, ring by ring from the hex itself outwards.
+    pub fn spiral(self, radius: u32) -> impl Iterator<Item = Hex> {
+        (0..=radius).flat_map(move |ring| self.ring(ring))
+    }
+
+    /// Returns the "odd-r" offset coordinate of the hex, where it's stored in a [Field].
+    pub fn to_offset(self) -> Coord {
+        Coord::new(self.q + (self.r - (self.r & 1)) / 2, self.r)
+    }
+
+    /// Returns the hex stored at an "odd-r" offset coordinate.
+    pub fn from_offset(coord: Coord) -> Self {
+        Self::new(coord.x - (coord.y - (coord.y & 1)) / 2, coord.y)
+    }
+
+    /// Returns the local position of the center of the hex, `cell_size` apart from the centers
+    /// of its neighbors.
+    pub fn to_local(self, cell_size: f32) -> Vec3 {
+        let x = (self.q as f32 + self.r as f32 / 2.0) * cell_size;
+        let z = self.r as f32 * ROW_SPACING * cell_size;
+        Vec3::new(x, 0.0, z)
+    }
+
+    /// Returns the hex containing the given local position.
+    pub fn from_local(local_pos: &Vec3, cell_size: f32) -> Self {
+        let r = local_pos.z / (ROW_SPACING * cell_size);
+        let q = local_pos.x / cell_size - r / 2.0;
+        Self::round(q, r)
+    }
+
+    /// Rounds fractional axial coordinates to the hex containing them.
+    pub fn round(q: f32, r: f32) -> Self {
+        let s = -q - r;
+        let (mut rq, mut rr, rs) = (q.round(), r.round(), s.round());
+        let (dq, dr, ds) = ((rq - q).abs(), (rr - r).abs(), (rs - s).abs());
+
+        // Fix the coordinate that was rounded the most, so q + r + s stays 0.
+        if dq > dr && dq > ds {
+            rq = -rr - rs;
+        } else if dr > ds {
+            rr = -rq - rs;
+        }
+
+        Self::new(rq as i32, rr as i32)
+    }
+}
+
+impl Add<Hex> for Hex {
+    type Output = Hex;
+
+    fn add(self, rhs: Hex) -> Self::Output {
+        Self {
+            q: self.q.wrapping_add(rhs.q),
+            r: self.r.wrapping_add(rhs.r),
+        }
+    }
+}
+
+impl Sub<Hex> for Hex {
+    type Output = Hex;
+
+    fn sub(self, rhs: Hex) -> Self::Output {
+        Self {
+            q: self.q.wrapping_sub(rhs.q),
+            r: self.r.wrapping_sub(rhs.r),
+        }
+    }
+}
+
+impl Mul<i32> for Hex {
+    type Output = Hex;
+
+    fn mul(self, rhs: i32) -> Self::Output {
+        Self {
+            q: self.q.wrapping_mul(rhs),
+            r: self.r.wrapping_mul(rhs),
+        }
+    }
+}
+
+impl From<Coord> for Hex {
+    fn from(coord: Coord) -> Self {
+        Self::from_offset(coord)
+    }
+}
+
+impl From<Hex> for Coord {
+    fn from(hex: Hex) -> Self {
+        hex.to_offset()
+    }
+}
+
+/// Returns the hex neighbors of an offset coordinate within bounds of given width and height.
+pub fn hex_neighbors(coord: &Coord, width: usize, height: usize) -> impl Iterator<Item = Coord> {
+    Hex::from_offset(*coord)
+        .neighbors()
+        .map(Hex::to_offset)
+        .filter(move |&c| c.x >= 0 && c.y >= 0 && c.x < width as i32 && c.y < height as i32)
+}
@@ -1,18 +1,24 @@
+mod chunked;
 mod coord;
 mod field;
+mod hex;
 
-use bevy::ecs::system::EntityCommands;
+use bevy::ecs::system::{Command, CommandQueue, EntityCommands};
+use bevy::utils::HashMap;
 
+pub use self::chunked::*;
 pub use self::coord::*;
 pub use self::field::*;
+pub use self::hex::*;
 use crate::prelude::*;
 
 pub struct GridPlugin;
 
 impl Plugin for GridPlugin {
     fn build(&self, app: &mut App) {
+        app.add_event::<GridCellRejected>();
         app.add_system_set_to_stage(
-            CoreStage::PostUpdate,
+            CoreStage::Last,
             ConditionSet::new()
                 .with_system(maintain_grid_storage_system)
                 .into(),
@@ -59,6 +65,40 @@ pub trait GridCommandsExt<'w, 's> {
         transform: &Transform,
         build_fn: fn(&mut EntityCommands<'_, '_, '_>, Coord),
     ) -> EntityCommands<'w, 's, 'a>;
+
+    /// Spawns a grid of hexagons, the cells are spawned at the offset [Coord] of their [Hex].
+    fn spawn_hex_grid<'a>(
+        &'a mut self,
+        width: usize,
+        height: usize,
+        cell_size: f32,
+        transform: &Transform,
+        build_fn: fn(&mut EntityCommands<'_, '_, '_>, Coord),
+    ) -> EntityCommands<'w, 's, 'a>;
+
+    /// Spawns a grid without cell entities, storing its cells in [CellData] on the grid entity.
+    /// Cells spawned as children of the grid later on are still tracked by it.
+    fn spawn_data_grid<'a>(
+        &'a mut self,
+        width: usize,
+        height: usize,
+        cell_size: f32,
+        transform: &Transform,
+    ) -> EntityCommands<'w, 's, 'a>;
+
+    /// Resizes a grid, keeping its cells at their coords. Cells that appear are spawned with
+    /// `build_fn`, unless the grid stores its cells in [CellData], & cells that disappear are
+    /// despawned. The flow fields of the grid are resized & computed again for their goals.
+    fn resize_grid(
+        &mut self,
+        grid: Entity,
+        width: usize,
+        height: usize,
+        build_fn: fn(&mut EntityCommands<'_, '_, '_>, Coord),
+    );
+
+    /// Despawns a grid along with all its cells & the flow fields linked to it by [FlowFieldGrid].
+    fn despawn_grid(&mut self, grid: Entity);
 }
 
 impl<'w, 's> GridCommandsExt<'w, 's> for Commands<'w, 's> {
@@ -70,17 +110,266 @@ impl<'w, 's> GridCommandsExt<'w, 's> for Commands<'w, 's> {
         transform: &Transform,
         child_build_fn: fn(&mut EntityCommands<'_, '_, '_>, Coord),
     ) -> EntityCommands<'w, 's, 'a> {
-        let entity = self
-            .spawn(GridBundle::new(width, height, cell_size, &transform))
-            .with_children(|parent| {
-                for coord in iter_coords(width, height) {
+        let bundle = GridBundle::new(width, height, cell_size, transform);
+        let entity = spawn_cells(self, bundle, child_build_fn);
+        self.entity(entity)
+    }
+
+    fn spawn_hex_grid<'a>(
+        &'a mut self,
+        width: usize,
+        height: usize,
+        cell_size: f32,
+        transform: &Transform,
+        child_build_fn: fn(&mut EntityCommands<'_, '_, '_>, Coord),
+    ) -> EntityCommands<'w, 's, 'a> {
+        let mut bundle = GridBundle::new(width, height, cell_size, transform);
+        bundle.grid.topology = Topology::Hex;
+        let entity = spawn_cells(self, bundle, child_build_fn);
+        self.entity(entity)
+    }
+
+    fn spawn_data_grid<'a>(
+        &'a mut self,
+        width: usize,
+        height: usize,
+        cell_size: f32,
+        transform: &Transform,
+    ) -> EntityCommands<'w, 's, 'a> {
+        let mut commands = self.spawn(GridBundle::new(width, height, cell_size, transform));
+        commands.insert(CellData::new(width, height));
+        commands
+    }
+
+    fn resize_grid(
+        &mut self,
+        grid: Entity,
+        width: usize,
+        height: usize,
+        build_fn: fn(&mut EntityCommands<'_, '_, '_>, Coord),
+    ) {
+        self.add(ResizeGrid {
+            grid,
+            width,
+            height,
+            build_fn,
+        });
+    }
+
+    fn despawn_grid(&mut self, grid: Entity) {
+        self.add(DespawnGrid { grid });
+    }
+}
+
+/// Spawns a grid with a child entity for every cell.
+fn spawn_cells(
+    commands: &mut Commands,
+    bundle: GridBundle,
+    child_build_fn: fn(&mut EntityCommands<'_, '_, '_>, Coord),
+) -> Entity {
+    let size = bundle.grid.data.size;
+    commands
+        .spawn(bundle)
+        .with_children(|parent| {
+            for coord in iter_coords(size.width, size.height) {
+                let mut child = parent.spawn(CellBundle::new(coord));
+                child_build_fn(&mut child, coord);
+            }
+        })
+        .id()
+}
+
+/// Resizes a grid, see [GridCommandsExt::resize_grid].
+struct ResizeGrid {
+    grid: Entity,
+    width: usize,
+    height: usize,
+    build_fn: fn(&mut EntityCommands<'_, '_, '_>, Coord),
+}
+
+impl Command for ResizeGrid {
+    fn write(self, world: &mut World) {
+        let (width, height) = (self.width, self.height);
+        let mut grid = match world.get_mut::<Grid>(self.grid) {
+            Some(grid) => grid,
+            None => {
+                log::error!("Grid entity {:?} not found.", self.grid);
+                return;
+            }
+        };
+
+        let old = grid.data.size;
+        let within = |coord: &Coord, size: FieldSize| {
+            coord.x < size.width as i32 && coord.y < size.height as i32
+        };
+        let new = FieldSize { width, height };
+
+        // Drop the cells that disappear, then resize the storage keeping the others in place.
+        let removed: Vec<Entity> = grid
+            .data
+            .iter_coords()
+            .filter(|coord| !within(coord, new))
+            .filter_map(|coord| grid.data[&coord])
+            .collect();
+        for entity in removed.iter() {
+            grid.remove_cell(*entity);
+        }
+        grid.data.resize(width, height);
+        for entity in removed {
+            if let Some(entity) = world.get_entity_mut(entity) {
+                entity.despawn_recursive();
+            }
+        }
+
+        let added: Vec<Coord> = iter_coords(width, height)
+            .filter(|coord| !within(coord, old))
+            .collect();
+        if let Some(mut cells) = world.get_mut::<CellData>(self.grid) {
+            cells.resize(width, height);
+        } else {
+            let mut spawned = Vec::with_capacity(added.len());
+            let mut queue = CommandQueue::default();
+            let mut commands = Commands::new(&mut queue, world);
+            commands.entity(self.grid).with_children(|parent| {
+                for coord in added {
                     let mut child = parent.spawn(CellBundle::new(coord));
-                    child_build_fn(&mut child, coord);
+                    (self.build_fn)(&mut child, coord);
+                    spawned.push((child.id(), coord));
                 }
-            })
-            .id();
+            });
+            queue.apply(world);
 
-        self.entity(entity)
+            if let Some(mut grid) = world.get_mut::<Grid>(self.grid) {
+                for (entity, coord) in spawned {
+                    grid.insert_cell(entity, &coord).ok();
+                }
+            }
+        }
+
+        // Resize the flow fields of the grid & compute them again over the new cells.
+        let mut recompute = vec![];
+        let mut flowfields = world.query::<(Entity, Option<&FlowFieldGrid>, &mut FlowField)>();
+        for (entity, link, mut flowfield) in flowfields.iter_mut(world) {
+            if FlowFieldGrid::of(entity, link) != self.grid {
+                continue;
+            }
+            flowfield.resize(width, height);
+            if flowfield.goals.is_empty() {
+                // Every goal was dropped, there is nothing left to flow towards.
+                *flowfield = flowfield.empty_copy();
+            }
+            recompute.push(ComputeFlowField {
+                goals: flowfield.goals.clone(),
+                grid_entity: entity,
+            });
+        }
+
+        for ev in recompute.iter() {
+            let mut entity = world.entity_mut(ev.grid_entity);
+            entity.remove::<FlowFieldTask>();
+            entity.remove::<FlowFieldProgress>();
+            if let Some(mut cache) = entity.get_mut::<FlowFieldCache>() {
+                cache.invalidate();
+            }
+        }
+        if let Some(mut events) = world.get_resource_mut::<Events<ComputeFlowField>>() {
+            for ev in recompute {
+                if !ev.goals.is_empty() {
+                    events.send(ev);
+                }
+            }
+        }
+
+        log::info!(
+            "Grid {:?} resized from {}x{} to {}x{}.",
+            self.grid,
+            old.width,
+            old.height,
+            width,
+            height
+        );
+    }
+}
+
+/// Despawns a grid, see [GridCommandsExt::despawn_grid].
+struct DespawnGrid {
+    grid: Entity,
+}
+
+impl Command for DespawnGrid {
+    fn write(self, world: &mut World) {
+        let linked: Vec<Entity> = world
+            .query::<(Entity, &FlowFieldGrid)>()
+            .iter(world)
+            .filter(|(_, link)| link.0 == self.grid)
+            .map(|(entity, _)| entity)
+            .collect();
+        for entity in linked {
+            if let Some(entity) = world.get_entity_mut(entity) {
+                entity.despawn_recursive();
+            }
+        }
+
+        match world.get_entity_mut(self.grid) {
+            Some(grid) => grid.despawn_recursive(),
+            None => log::error!("Grid entity {:?} not found.", self.grid),
+        }
+    }
+}
+
+/// The shape of the cells of a [Grid].
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Topology {
+    /// Square cells with 8 neighbors.
+    #[default]
+    Square,
+    /// Pointy-top hexagons with 6 neighbors, stored at the offset [Coord] of their [Hex].
+    Hex,
+}
+
+impl Topology {
+    /// Returns the neighbors of a coordinate within bounds of given width and height.
+    pub fn neighbors<'a>(
+        &self,
+        coord: &'a Coord,
+        width: usize,
+        height: usize,
+    ) -> impl Iterator<Item = Coord> + 'a {
+        let (square, hex) = match self {
+            Topology::Square => (Some(neighbors8(coord, width, height)), None),
+            Topology::Hex => (None, Some(hex_neighbors(coord, width, height))),
+        };
+        square
+            .into_iter()
+            .flatten()
+            .chain(hex.into_iter().flatten())
+    }
+
+    /// Returns the local position of the given coordinate.
+    pub fn coord_to_local(&self, coord: &Coord, cell_size: f32) -> Vec3 {
+        match self {
+            Topology::Square => coord_to_local(coord, cell_size),
+            Topology::Hex => Hex::from_offset(*coord).to_local(cell_size),
+        }
+    }
+
+    /// Returns the coordinate for the given local position.
+    pub fn local_to_coord(&self, local_pos: &Vec3, cell_size: f32) -> Coord {
+        match self {
+            Topology::Square => local_to_coord(local_pos, cell_size),
+            Topology::Hex => Hex::from_local(local_pos, cell_size).to_offset(),
+        }
+    }
+
+    /// Returns the direction from one cell to another in the local XZ plane, in cells. Square
+    /// directions aren't normalized, a diagonal step is `(1, 1)`.
+    pub fn direction(&self, from: &Coord, to: &Coord) -> Vec2 {
+        match self {
+            Topology::Square => Vec2::from(*to - *from),
+            Topology::Hex => {
+                (self.coord_to_local(to, 1.0) - self.coord_to_local(from, 1.0)).pos_2d()
+            }
+        }
     }
 }
 
@@ -89,6 +378,11 @@ impl<'w, 's> GridCommandsExt<'w, 's> for Commands<'w, 's> {
 pub struct Grid {
     pub data: Field<Option<Entity>>,
     pub cell_size: f32,
+    pub topology: Topology,
+    /// The coordinate every cell entity is stored at.
+    cells: HashMap<Entity, Coord>,
+    /// Cell entities that couldn't be stored, retried whenever a cell of the grid is removed.
+    rejected: Vec<Entity>,
 }
 
 impl Grid {
@@ -101,24 +395,39 @@ impl Grid {
         }
     }
 
+    /// Sets the shape of the cells.
+    pub fn with_topology(mut self, topology: Topology) -> Self {
+        self.topology = topology;
+        self
+    }
+
     /// Returns the world position of the given coordinate.
     pub fn coord_to_world(&self, coord: &Coord, grid_transform: &Transform) -> Vec3 {
-        coord_to_world(coord, self.cell_size, grid_transform)
+        let local_pos = self.coord_to_local(coord);
+        let point = grid_transform.compute_matrix() * local_pos.extend(1.0);
+        Vec3::new(point.x, point.y, point.z)
     }
 
     /// Returns the local position of the given coordinate.
     pub fn coord_to_local(&self, coord: &Coord) -> Vec3 {
-        coord_to_local(coord, self.cell_size)
+        self.topology.coord_to_local(coord, self.cell_size)
     }
 
     /// Returns the coordinate for the given world position.
     pub fn world_to_coord(&self, world_pos: &Vec3, grid_transform: &Transform) -> Coord {
-        world_to_coord(world_pos, self.cell_size, grid_transform)
+        let local_pos = grid_transform.compute_matrix().inverse() * world_pos.extend(1.0);
+        self.local_to_coord(&local_pos.xyz())
     }
 
     /// Returns the coordinate for the given local position.
     pub fn local_to_coord(&self, local_pos: &Vec3) -> Coord {
-        local_to_coord(local_pos, self.cell_size)
+        self.topology.local_to_coord(local_pos, self.cell_size)
+    }
+
+    /// Returns the neighbors of a coordinate within the grid.
+    pub fn neighbors<'a>(&self, coord: &'a Coord) -> impl Iterator<Item = Coord> + 'a {
+        let size = self.data.size;
+        self.topology.neighbors(coord, size.width, size.height)
     }
 
     /// Returns true if the given coordinate is within the grid dimensions.
@@ -130,6 +439,37 @@ impl Grid {
     pub fn get(&self, coord: &Coord) -> Option<Entity> {
         self.data[coord]
     }
+
+    /// Returns the coordinate a cell entity is stored at, if it's a cell of the grid.
+    pub fn coord_of(&self, entity: Entity) -> Option<Coord> {
+        self.cells.get(&entity).copied()
+    }
+
+    /// Stores a cell entity at the given coordinate, moving it if it's stored elsewhere.
+    /// Fails if the coordinate is out of bounds or already holds another entity.
+    pub fn insert_cell(&mut self, entity: Entity, coord: &Coord) -> Result<(), GridError> {
+        if !self.within_bounds(coord) {
+            return Err(GridError::OutOfBounds);
+        }
+        match self.data[coord] {
+            Some(existing) if existing != entity => Err(GridError::DuplicateCell(existing)),
+            _ => {
+                self.remove_cell(entity);
+                self.data[coord] = Some(entity);
+                self.cells.insert(entity, *coord);
+                Ok(())
+            }
+        }
+    }
+
+    /// Removes a cell entity from the grid, returns the coordinate it was stored at.
+    pub fn remove_cell(&mut self, entity: Entity) -> Option<Coord> {
+        let coord = self.cells.remove(&entity)?;
+        if self.data.within_bounds(&coord) && self.data[&coord] == Some(entity) {
+            self.data[&coord] = None;
+        }
+        Some(coord)
+    }
 }
 
 #[inline]
@@ -159,13 +499,106 @@ pub fn local_to_coord(local_pos: &Vec3, cell_size: f32) -> Coord {
     Coord::new(x, y)
 }
 
+/// Sent when a cell entity can't be stored in the grid it's a child of.
+#[derive(Debug, Clone)]
+pub struct GridCellRejected {
+    pub grid_entity: Entity,
+    pub entity: Entity,
+    pub coord: Coord,
+    pub error: GridError,
+}
+
+/// Why a cell entity can't be stored in its grid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GridError {
+    /// The coordinate of the cell is out of bounds of the grid.
+    OutOfBounds,
+    /// The coordinate already holds the given entity, which keeps the cell.
+    DuplicateCell(Entity),
+}
+
+/// Query filter for cells that were added, moved or re-parented.
+pub type CellMoved = Or<(Changed<Coord>, Changed<Parent>)>;
+
+/// Keeps the cell entities stored in every grid in sync with their [Coord] & [Parent]: cells
+/// are stored when they're added, moved when their coordinate changes, & removed when they're
+/// despawned, lose their coordinate or are re-parented to another entity. Rejected cells are
+/// stored once the cell holding their coordinate is gone.
+///
+/// Runs in [CoreStage::Last] to see the cells removed during the frame, removals are only
+/// tracked until the end of the frame: cells despawned after it, later in [CoreStage::Last],
+/// stay stored in their grid.
 fn maintain_grid_storage_system(
-    mut grids: Query<&mut Grid>,
-    query: Query<(Entity, &Parent, &Coord), Changed<Coord>>,
+    mut grids: Query<(Entity, &mut Grid)>,
+    changed: Query<(Entity, &Parent, &Coord), CellMoved>,
+    cells: Query<(&Parent, &Coord)>,
+    removed_coords: RemovedComponents<Coord>,
+    removed_parents: RemovedComponents<Parent>,
+    mut ev_rejected: EventWriter<GridCellRejected>,
 ) {
-    for (entity, parent, coord) in query.iter() {
-        if let Ok(mut grid) = grids.get_mut(parent.get()) {
-            grid.data[&coord] = Some(entity);
+    // Clear the old slots first, so cells swapping coordinates don't collide.
+    let removed: Vec<Entity> = removed_coords
+        .iter()
+        .chain(removed_parents.iter())
+        .chain(changed.iter().map(|(entity, ..)| entity))
+        .collect();
+    if removed.is_empty() {
+        return;
+    }
+    for (_, mut grid) in grids.iter_mut() {
+        if removed
+            .iter()
+            .any(|entity| grid.cells.contains_key(entity) || grid.rejected.contains(entity))
+        {
+            for entity in removed.iter() {
+                grid.remove_cell(*entity);
+            }
+            grid.rejected.retain(|entity| !removed.contains(entity));
         }
     }
+
+    for (entity, parent, coord) in changed.iter() {
+        let (grid_entity, mut grid) = match grids.get_mut(parent.get()) {
+            Ok(grid) => grid,
+            Err(_) => continue,
+        };
+
+        if let Err(error) = grid.insert_cell(entity, coord) {
+            log::error!(
+                "Cell {:?} can't be stored at {:?} of grid {:?}: {:?}.",
+                entity,
+                coord,
+                grid_entity,
+                error
+            );
+            grid.rejected.push(entity);
+            ev_rejected.send(GridCellRejected {
+                grid_entity,
+                entity,
+                coord: *coord,
+                error,
+            });
+        }
+    }
+
+    // Retry the rejected cells, their coordinate may have been freed.
+    for (grid_entity, mut grid) in grids.iter_mut() {
+        if grid.rejected.is_empty() {
+            continue;
+        }
+
+        let grid = &mut *grid;
+        grid.rejected.retain(|entity| match cells.get(*entity) {
+            Ok((parent, coord)) if parent.get() == grid_entity => {
+                if grid.data.within_bounds(coord) && grid.data[coord].is_none() {
+                    grid.data[coord] = Some(*entity);
+                    grid.cells.insert(*entity, *coord);
+                    false
+                } else {
+                    true
+                }
+            }
+            _ => false,
+        });
+    }
 }
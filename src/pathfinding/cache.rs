@@ -0,0 +1,159 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use crate::prelude::*;
+
+/// A cache of computed flow fields for the grid it's attached to, keyed by goals.
+/// Entries are shared [CompactFlowField]s, evicted least-recently-used first & invalidated
+/// for the same changes the flow field of the grid is repaired for, see [FlowFieldChanges].
+///
+/// Agents look the field of their goals up with [FlowFieldCache::flowfield]. A [ComputeFlowField]
+/// for goals that are already cached isn't computed again & leaves the [FlowField] of the grid
+/// as it is, a [FlowFieldCacheHit] is sent instead of a [FlowFieldComputed].
+/// Lookups only take the cache by reference, so they don't mark it as changed.
+#[derive(Component, Debug)]
+pub struct FlowFieldCache {
+    /// The maximum number of flow fields kept.
+    pub capacity: usize,
+    /// Bumped whenever the cache is invalidated.
+    pub version: u64,
+    entries: Vec<CachedFlowField>,
+    tick: AtomicU64,
+}
+
+/// A flow field in a [FlowFieldCache].
+#[derive(Debug)]
+pub struct CachedFlowField {
+    /// The goals of the flow field, sorted.
+    pub goals: Vec<Goal>,
+    /// The cost version the flow field was computed for.
+    pub version: u64,
+    pub flowfield: Arc<CompactFlowField>,
+    pub stats: FlowFieldStats,
+    last_used: AtomicU64,
+}
+
+impl Default for FlowFieldCache {
+    fn default() -> Self {
+        Self::new(16)
+    }
+}
+
+impl FlowFieldCache {
+    /// Creates an empty cache keeping up to `capacity` flow fields.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            version: 0,
+            entries: vec![],
+            tick: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the number of cached flow fields.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if no flow field is cached.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the cached flow field for the given goals & marks it as recently used.
+    pub fn get(&self, goals: &[Goal]) -> Option<&CachedFlowField> {
+        let key = cache_key(goals);
+        let entry = self
+            .entries
+            .iter()
+            .find(|entry| entry.version == self.version && entry.goals == key)?;
+        let tick = self.tick.fetch_add(1, Ordering::Relaxed) + 1;
+        entry.last_used.store(tick, Ordering::Relaxed);
+        Some(entry)
+    }
+
+    /// Returns the shared flow field for the given goals, if cached.
+    pub fn flowfield(&self, goals: &[Goal]) -> Option<Arc<CompactFlowField>> {
+        self.get(goals).map(|entry| entry.flowfield.clone())
+    }
+
+    /// Returns true if a flow field for the given goals is cached.
+    pub fn contains(&self, goals: &[Goal]) -> bool {
+        let key = cache_key(goals);
+        self.entries
+            .iter()
+            .any(|entry| entry.version == self.version && entry.goals == key)
+    }
+
+    /// Caches a flow field computed for the current cost version, evicting the least recently
+    /// used flow field if the cache is full.
+    pub fn insert(&mut self, flowfield: Arc<CompactFlowField>, stats: FlowFieldStats) {
+        let key = cache_key(&flowfield.goals);
+        self.entries.retain(|entry| entry.goals != key);
+
+        if self.entries.len() >= self.capacity {
+            if let Some(index) = self
+                .entries
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, entry)| entry.last_used.load(Ordering::Relaxed))
+                .map(|(index, _)| index)
+            {
+                self.entries.swap_remove(index);
+            }
+        }
+
+        let tick = self.tick.fetch_add(1, Ordering::Relaxed) + 1;
+        self.entries.push(CachedFlowField {
+            goals: key,
+            version: self.version,
+            flowfield,
+            stats,
+            last_used: AtomicU64::new(tick),
+        });
+    }
+
+    /// Bumps the cost version & drops every cached flow field.
+    pub fn invalidate(&mut self) {
+        self.version += 1;
+        self.entries.clear();
+    }
+}
+
+/// Returns the goals in a canonical order, so the same goal set always maps to the same entry.
+fn cache_key(goals: &[Goal]) -> Vec<Goal> {
+    let mut key = goals.to_vec();
+    key.sort_by_key(|goal| (goal.coord, goal.cost));
+    key.dedup();
+    key
+}
+
+/// Invalidates the caches of flow fields that are repaired, see [FlowFieldChanges].
+pub fn invalidate_flowfield_cache(
+    mut caches: Query<(Entity, &FlowField, &mut FlowFieldCache)>,
+    changes: FlowFieldChanges,
+) {
+    for (entity, flowfield, mut cache) in caches.iter_mut() {
+        if !changes.cells(entity, flowfield).is_empty() {
+            log::debug!("Invalidate flowfield cache of {:?}.", entity);
+            cache.invalidate();
+        }
+    }
+}
+
+/// Caches every computed flow field of grids with a [FlowFieldCache].
+pub fn cache_computed_flowfields(
+    mut ev_computed: EventReader<FlowFieldComputed>,
+    mut grids: Query<(&FlowField, &mut FlowFieldCache)>,
+) {
+    for ev in ev_computed.iter() {
+        if let Ok((flowfield, mut cache)) = grids.get_mut(ev.grid_entity) {
+            // Skip flow fields whose goals changed since they were computed.
+            if cache_key(&flowfield.goals) == cache_key(&ev.goals) {
+                cache.insert(Arc::new(flowfield.compact()), ev.stats);
+            }
+        }
+    }
+}
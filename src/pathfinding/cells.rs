@@ -0,0 +1,167 @@
+use bevy::{ecs::system::SystemParam, utils::HashMap};
+
+use crate::prelude::*;
+
+pub struct CellDataPlugin;
+
+impl Plugin for CellDataPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_to_stage(CoreStage::First, publish_cell_changes);
+    }
+}
+
+/// The costs & terrain of every cell of the grid it's attached to, stored in fields on the grid
+/// entity instead of on one entity per cell. Pathfinding reads cells from here, cell entities
+/// are optional & only needed for cells with behaviour of their own.
+/// Spawn the grid with [GridCommandsExt::spawn_data_grid] to skip the cell entities.
+///
+/// Cells are changed through [CellData::set_cost] & [CellData::set_terrain], the changes of a
+/// frame are published in `changed` at the start of the next one, so flow fields, regions &
+/// sector graphs are repaired like after changing the [Cost] of a cell entity.
+#[derive(Component, Debug, Default, Clone)]
+pub struct CellData {
+    costs: Field<Cost>,
+    terrain: Field<Terrain>,
+    /// The cells whose cost or terrain changed during the last frame.
+    pub changed: Vec<Coord>,
+    pending: Vec<Coord>,
+}
+
+impl CellData {
+    /// Creates the cell data of a grid of the given size, with empty ground cells.
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            costs: Field::new(width, height, vec![Cost::EMPTY; width * height]),
+            terrain: Field::new(width, height, vec![Terrain::Ground; width * height]),
+            ..default()
+        }
+    }
+
+    /// Returns the cost of every cell.
+    pub fn costs(&self) -> &Field<Cost> {
+        &self.costs
+    }
+
+    /// Returns the terrain of every cell.
+    pub fn terrain(&self) -> &Field<Terrain> {
+        &self.terrain
+    }
+
+    /// Returns the cost of a cell, `None` outside of the grid.
+    pub fn cost(&self, coord: &Coord) -> Option<Cost> {
+        self.costs.within_bounds(coord).then(|| self.costs[coord])
+    }
+
+    /// Returns the terrain of a cell, `None` outside of the grid.
+    pub fn terrain_at(&self, coord: &Coord) -> Option<Terrain> {
+        self.terrain
+            .within_bounds(coord)
+            .then(|| self.terrain[coord])
+    }
+
+    /// Resizes the cell data, keeping every cell within the new dimensions at its coordinate.
+    /// New cells are empty ground.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.costs.resize(width, height);
+        self.terrain.resize(width, height);
+        let costs = &self.costs;
+        self.changed.retain(|coord| costs.within_bounds(coord));
+        self.pending.retain(|coord| costs.within_bounds(coord));
+    }
+
+    /// Sets the cost of a cell, returns true if it changed.
+    pub fn set_cost(&mut self, coord: &Coord, cost: Cost) -> bool {
+        if !self.costs.within_bounds(coord) || self.costs[coord] == cost {
+            return false;
+        }
+        self.costs[coord] = cost;
+        self.pending.push(*coord);
+        true
+    }
+
+    /// Sets the terrain of a cell, returns true if it changed.
+    pub fn set_terrain(&mut self, coord: &Coord, terrain: Terrain) -> bool {
+        if !self.terrain.within_bounds(coord) || self.terrain[coord] == terrain {
+            return false;
+        }
+        self.terrain[coord] = terrain;
+        self.pending.push(*coord);
+        true
+    }
+}
+
+/// Returns the [Cost] of the cell at the given coordinate, from the [CellData] of the grid if it
+/// has any, from the cell entity otherwise.
+pub fn cell_cost(
+    grid: &Grid,
+    cells: Option<&CellData>,
+    costs: &Query<&Cost>,
+    coord: &Coord,
+) -> Option<Cost> {
+    match cells {
+        Some(cells) => cells.cost(coord),
+        None => grid
+            .get(coord)
+            .and_then(|entity| costs.get(entity).ok())
+            .copied(),
+    }
+}
+
+/// The cells whose [Cost] or [Terrain] changed since the last run of the system, on cell
+/// entities & in [CellData].
+#[derive(SystemParam)]
+pub struct ChangedCells<'w, 's> {
+    entities: Query<'w, 's, (&'static Coord, &'static Parent), CellCostChanged>,
+    data: Query<'w, 's, (Entity, &'static CellData), Changed<CellData>>,
+}
+
+impl<'w, 's> ChangedCells<'w, 's> {
+    /// Returns the changed cells per grid entity.
+    pub fn by_grid(&self) -> HashMap<Entity, Vec<Coord>> {
+        let mut changed_by_grid: HashMap<Entity, Vec<Coord>> = HashMap::default();
+        for (coord, parent) in self.entities.iter() {
+            changed_by_grid
+                .entry(parent.get())
+                .or_default()
+                .push(*coord);
+        }
+        for (entity, cells) in self.data.iter() {
+            if !cells.changed.is_empty() {
+                changed_by_grid
+                    .entry(entity)
+                    .or_default()
+                    .extend(cells.changed.iter().copied());
+            }
+        }
+        changed_by_grid
+    }
+
+    /// Returns the changed cells of the given grid entity.
+    pub fn of_grid(&self, grid: Entity) -> Vec<Coord> {
+        let mut changed: Vec<Coord> = self
+            .entities
+            .iter()
+            .filter(|(_, parent)| parent.get() == grid)
+            .map(|(coord, _)| *coord)
+            .collect();
+        if let Ok((_, cells)) = self.data.get(grid) {
+            changed.extend(cells.changed.iter().copied());
+        }
+        changed
+    }
+}
+
+/// Publishes the cells changed during the last frame.
+fn publish_cell_changes(mut grids: Query<&mut CellData>) {
+    for mut cells in grids.iter_mut() {
+        // Only touch the data if anything changed, or to clear the last changes.
+        if cells.pending.is_empty() && cells.changed.is_empty() {
+            continue;
+        }
+
+        let cells = &mut *cells;
+        cells.changed = std::mem::take(&mut cells.pending);
+        cells.changed.sort_unstable();
+        cells.changed.dedup();
+    }
+}
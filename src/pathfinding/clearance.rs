@@ -0,0 +1,164 @@
+use std::collections::VecDeque;
+
+use crate::prelude::*;
+
+pub struct ClearancePlugin;
+
+impl Plugin for ClearancePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set_to_stage(
+            CoreStage::PreUpdate,
+            ConditionSet::new()
+                .with_system(
+                    update_clearance
+                        .label(SystemLabels::FlowFieldLayers)
+                        .before(compute_flowfield)
+                        .before(repair_flowfield),
+                )
+                .into(),
+        );
+    }
+}
+
+/// The clearance of every cell of the grid of the flow field it's attached to, as seen by the
+/// movement class of the flow field. Clearance is the distance in cells to the closest blocked
+/// cell or the edge of the grid: 0 for blocked cells, 1 next to an obstacle.
+///
+/// Flow fields with a `min_clearance` only route through cells with at least that clearance.
+#[derive(Component, Debug, Default, Clone)]
+pub struct Clearance {
+    pub clearance: Field<u8>,
+    /// The cells whose clearance changed with the last update.
+    pub changed: Vec<Coord>,
+}
+
+impl Clearance {
+    /// Returns the clearance needed by an agent `cells` wide, centered on a cell.
+    pub fn for_size(cells: u8) -> u8 {
+        cells / 2 + 1
+    }
+
+    /// Returns the clearance of a cell, 0 outside of the grid.
+    pub fn get(&self, coord: &Coord) -> u8 {
+        if self.clearance.within_bounds(coord) {
+            self.clearance[coord]
+        } else {
+            0
+        }
+    }
+
+    /// Returns true if the clearance has been built for a grid of the given size.
+    pub fn is_built_for(&self, size: &FieldSize) -> bool {
+        self.clearance.size == *size
+    }
+
+    /// Computes the clearance of every cell of a grid of the given size & topology with a
+    /// brushfire transform, spreading outwards from blocked cells & the edge of the grid.
+    pub fn brushfire(
+        size: FieldSize,
+        topology: Topology,
+        cost_at: impl Fn(&Coord) -> Option<Cost>,
+    ) -> Field<u8> {
+        let (width, height) = (size.width, size.height);
+        let mut clearance = Field::new(width, height, vec![u8::MAX; width * height]);
+        let mut queue = VecDeque::new();
+
+        // Obstacles first, then the edge, so the queue stays sorted by clearance.
+        for coord in clearance.iter_coords().collect::<Vec<_>>() {
+            if cost_at(&coord).and_then(Cost::passable).is_none() {
+                clearance[&coord] = 0;
+                queue.push_back(coord);
+            }
+        }
+        for coord in clearance.iter_coords().collect::<Vec<_>>() {
+            let edge = coord.x == 0
+                || coord.y == 0
+                || coord.x == width as i32 - 1
+                || coord.y == height as i32 - 1;
+            if edge && clearance[&coord] > 1 {
+                clearance[&coord] = 1;
+                queue.push_back(coord);
+            }
+        }
+
+        while let Some(coord) = queue.pop_front() {
+            let next = clearance[&coord].saturating_add(1);
+            for neighbor in topology.neighbors(&coord, width, height) {
+                if clearance[&neighbor] > next {
+                    clearance[&neighbor] = next;
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+
+        clearance
+    }
+}
+
+/// Returns the cost of a cell, blocking cells with less than `min_clearance`, wrapping another
+/// cost lookup.
+pub fn with_clearance<'a>(
+    cost_at: impl Fn(&Coord) -> Option<Cost> + 'a,
+    clearance: Option<&'a Clearance>,
+    min_clearance: u8,
+) -> impl Fn(&Coord) -> Option<Cost> + 'a {
+    move |coord| {
+        let cost = cost_at(coord)?;
+        match clearance {
+            Some(clearance) if clearance.get(coord) < min_clearance => Some(Cost::Blocked),
+            _ => Some(cost),
+        }
+    }
+}
+
+/// Builds the clearance of new or resized grids & rebuilds it where any cell cost changed.
+fn update_clearance(
+    mut params: ParamSet<(FlowFieldGrids, Query<&mut Clearance>)>,
+    flowfields: Query<(Entity, &FlowField), With<Clearance>>,
+    changed: ChangedCells,
+) {
+    let changed = changed.by_grid();
+
+    let grids = params.p0();
+    let mut built = vec![];
+    for (entity, flowfield) in flowfields.iter() {
+        let (grid, cost_at) = match grids.terrain_cost_at(entity, flowfield.movement) {
+            Some(grid) => grid,
+            None => continue,
+        };
+
+        let outdated = !grids
+            .clearances
+            .get(entity)
+            .is_ok_and(|clearance| clearance.is_built_for(&grid.data.size))
+            || changed.contains_key(&grids.grid_entity(entity))
+            || (flowfield.movement.is_some() && grids.terrain_costs.is_changed());
+        if outdated {
+            let clearance = Clearance::brushfire(grid.data.size, grid.topology, cost_at);
+            built.push((entity, clearance));
+        }
+    }
+
+    let mut clearances = params.p1();
+    for (entity, clearance) in built {
+        let mut current = match clearances.get_mut(entity) {
+            Ok(current) => current,
+            Err(_) => continue,
+        };
+
+        let changed: Vec<Coord> = clearance
+            .iter_coords()
+            .filter(|coord| current.get(coord) != clearance[coord])
+            .collect();
+
+        if !changed.is_empty() || !current.is_built_for(&clearance.size) {
+            log::debug!(
+                "Clearance {:?} changed for {} cell(s).",
+                entity,
+                changed.len()
+            );
+            current.clearance = clearance;
+            current.changed = changed;
+        }
+    }
+}
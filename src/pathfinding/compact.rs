@@ -0,0 +1,182 @@
+use crate::prelude::*;
+
+/// A 1 byte flow direction of a cell, see [CompactFlowField].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FlowCode(pub u8);
+
+impl FlowCode {
+    /// The cell has no flow, it's blocked or can't reach a goal.
+    pub const NONE: Self = Self(u8::MAX);
+    /// The cell is a goal.
+    pub const GOAL: Self = Self(8);
+    /// Codes from here on flow directly to the goal at index `code - VISIBLE`.
+    const VISIBLE: u8 = 16;
+
+    /// Encodes one of the 8 neighbor directions.
+    pub fn from_dir(dir: Coord) -> Self {
+        match NEIGHBORS_8.iter().position(|neighbor| *neighbor == dir) {
+            Some(index) => Self(index as u8),
+            None => Self::GOAL,
+        }
+    }
+
+    /// Encodes the hex neighbor direction closest to the given flow, see [HEX_NEIGHBORS].
+    pub fn from_hex_flow(coord: &Coord, flow: Vec2) -> Self {
+        let hex = Hex::from_offset(*coord);
+        let closest = HEX_NEIGHBORS.iter().enumerate().max_by(|(_, a), (_, b)| {
+            let a = Topology::Hex.direction(coord, &(hex + **a).to_offset());
+            let b = Topology::Hex.direction(coord, &(hex + **b).to_offset());
+            flow.dot(a).total_cmp(&flow.dot(b))
+        });
+        match closest {
+            Some((index, _)) => Self(index as u8),
+            None => Self::GOAL,
+        }
+    }
+
+    /// Encodes a line of sight to the goal at the given index.
+    pub fn visible(goal_index: usize) -> Option<Self> {
+        u8::try_from(goal_index + Self::VISIBLE as usize)
+            .ok()
+            .filter(|code| *code != Self::NONE.0)
+            .map(Self)
+    }
+
+    /// Returns the neighbor direction, if the code is one.
+    pub fn dir(self) -> Option<Coord> {
+        NEIGHBORS_8.get(self.0 as usize).copied()
+    }
+
+    /// Returns the neighbor of a cell the code flows to on a grid with the given topology, if the
+    /// code is a neighbor direction.
+    pub fn neighbor(self, coord: &Coord, topology: Topology) -> Option<Coord> {
+        match topology {
+            Topology::Square => self.dir().map(|dir| *coord + dir),
+            Topology::Hex => HEX_NEIGHBORS
+                .get(self.0 as usize)
+                .map(|dir| (Hex::from_offset(*coord) + *dir).to_offset()),
+        }
+    }
+
+    /// Returns the index of the visible goal, if the code is a line of sight.
+    pub fn visible_goal(self) -> Option<usize> {
+        if self.0 >= Self::VISIBLE && self != Self::NONE {
+            Some((self.0 - Self::VISIBLE) as usize)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for FlowCode {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+/// Integration value of an unreachable cell in a [CompactFlowField].
+pub const UNREACHABLE: u16 = u16::MAX;
+
+/// A flow field stored in 3 bytes per cell: a [FlowCode] & a `u16` integration value.
+/// Integration values too large for a `u16` saturate at `UNREACHABLE - 1`, the flow itself is
+/// lossless.
+#[derive(Component, Default, Debug, Clone)]
+pub struct CompactFlowField {
+    pub goals: Vec<Goal>,
+    pub diagonal: DiagonalMovement,
+    pub topology: Topology,
+    pub flow: Field<FlowCode>,
+    pub integration: Field<u16>,
+}
+
+impl CompactFlowField {
+    /// Returns the flow direction of a cell, decoded the same as [FlowField::get].
+    pub fn get(&self, coord: &Coord) -> Option<Vec2> {
+        if !self.flow.within_bounds(coord) {
+            return None;
+        }
+
+        let code = self.flow[coord];
+        if code == FlowCode::NONE {
+            None
+        } else if let Some(neighbor) = code.neighbor(coord, self.topology) {
+            Some(self.topology.direction(coord, &neighbor))
+        } else if let Some(goal) = code.visible_goal().and_then(|index| self.goals.get(index)) {
+            Some(Vec2::from(goal.coord - *coord).normalize_or_zero())
+        } else {
+            Some(Vec2::ZERO)
+        }
+    }
+
+    /// Returns the integration value of a cell, `None` if it's unreachable.
+    pub fn integration(&self, coord: &Coord) -> Option<i32> {
+        if !self.integration.within_bounds(coord) || self.integration[coord] == UNREACHABLE {
+            None
+        } else {
+            Some(self.integration[coord] as i32)
+        }
+    }
+}
+
+impl From<&FlowField> for CompactFlowField {
+    fn from(flowfield: &FlowField) -> Self {
+        let size = flowfield.flow.size;
+
+        let flow = flowfield
+            .flow
+            .iter_coords()
+            .map(|coord| encode_flow(flowfield, &coord))
+            .collect();
+        let integration = flowfield
+            .integration
+            .iter()
+            .map(|value| match value {
+                Some(value) => (*value).clamp(0, UNREACHABLE as i32 - 1) as u16,
+                None => UNREACHABLE,
+            })
+            .collect();
+
+        Self {
+            goals: flowfield.goals.clone(),
+            diagonal: flowfield.diagonal,
+            topology: flowfield.topology,
+            flow: Field::new(size.width, size.height, flow),
+            integration: Field::new(size.width, size.height, integration),
+        }
+    }
+}
+
+/// Encodes the flow of a cell, the vector is one of the 8 directions (6 on hex grids) unless the
+/// cell has a line of sight to a goal.
+fn encode_flow(flowfield: &FlowField, coord: &Coord) -> FlowCode {
+    let flow = match flowfield.flow[coord] {
+        Some(flow) => flow,
+        None => return FlowCode::NONE,
+    };
+
+    if flowfield.uses_line_of_sight() && flowfield.visible.within_bounds(coord) {
+        let goal_index = flowfield.visible[coord]
+            .and_then(|goal| flowfield.goals.iter().position(|g| g.coord == goal));
+        if let Some(code) = goal_index.and_then(FlowCode::visible) {
+            if flow != Vec2::ZERO {
+                return code;
+            }
+        }
+    }
+
+    if flow == Vec2::ZERO {
+        FlowCode::GOAL
+    } else if flowfield.topology == Topology::Hex {
+        FlowCode::from_hex_flow(coord, flow)
+    } else {
+        // Direct vectors that can't be encoded are snapped to the closest direction.
+        FlowCode::from_dir(Coord::new(flow.x.round() as i32, flow.y.round() as i32))
+    }
+}
+
+impl FlowField {
+    /// Returns a compact copy of the flow field, see [CompactFlowField].
+    pub fn compact(&self) -> CompactFlowField {
+        self.into()
+    }
+}
@@ -0,0 +1,146 @@
+use std::marker::PhantomData;
+
+use crate::prelude::*;
+
+/// Adds a [Congestion] layer to grids, counting the entities with the component `T` per cell.
+pub struct CongestionPlugin<T> {
+    _marker: PhantomData<T>,
+}
+
+impl<T> Default for CongestionPlugin<T> {
+    fn default() -> Self {
+        Self {
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Component> Plugin for CongestionPlugin<T> {
+    fn build(&self, app: &mut App) {
+        app.add_system_set_to_stage(
+            CoreStage::PreUpdate,
+            ConditionSet::new()
+                .with_system(
+                    update_congestion::<T>
+                        .label(SystemLabels::FlowFieldLayers)
+                        .before(invalidate_flowfield_cache)
+                        .before(compute_flowfield)
+                        .before(repair_flowfield),
+                )
+                .into(),
+        );
+    }
+}
+
+/// A density layer for the grid it's attached to. Periodically counts the agents in every cell,
+/// flow fields add extra cost to crowded cells so they spread crowds across parallel routes.
+///
+/// The extra cost is blended into the cost of the cells by the flow fields of the grid, see
+/// [with_congestion]. The [Cost] of the cells is left as it is.
+#[derive(Component, Debug, Clone)]
+pub struct Congestion {
+    /// Extra cost per agent above the threshold.
+    pub cost_per_agent: u8,
+    /// The maximum extra cost of a cell.
+    pub max_cost: u8,
+    /// The number of agents a cell holds before it's crowded.
+    pub threshold: u16,
+    /// How often the layer is refreshed.
+    pub timer: Timer,
+    /// The number of agents in every cell at the last refresh, only the chunks holding agents
+    /// are allocated.
+    pub occupancy: ChunkedField<u16>,
+    /// The cells whose extra cost changed with the last refresh.
+    pub changed: Vec<Coord>,
+}
+
+impl Default for Congestion {
+    fn default() -> Self {
+        Self::new(1, 8, 64, 1.0)
+    }
+}
+
+impl Congestion {
+    /// Creates a congestion layer refreshed every `interval` seconds.
+    pub fn new(threshold: u16, cost_per_agent: u8, max_cost: u8, interval: f32) -> Self {
+        Self {
+            cost_per_agent,
+            max_cost,
+            threshold,
+            timer: Timer::from_seconds(interval, TimerMode::Repeating),
+            occupancy: default(),
+            changed: vec![],
+        }
+    }
+
+    /// Returns the extra cost of a cell holding the given number of agents.
+    pub fn extra_cost(&self, agents: u16) -> u8 {
+        let crowd = agents.saturating_sub(self.threshold);
+        (crowd as u32 * self.cost_per_agent as u32).min(self.max_cost as u32) as u8
+    }
+
+    /// Returns the extra cost of a cell at the last refresh, 0 outside of the grid.
+    pub fn get(&self, coord: &Coord) -> u8 {
+        self.extra_cost(self.occupancy[coord])
+    }
+}
+
+/// Returns the cost of a cell with the extra cost of its agents added, wrapping another cost
+/// lookup. Blocked cells stay blocked.
+pub fn with_congestion<'a>(
+    cost_at: impl Fn(&Coord) -> Option<Cost> + 'a,
+    congestion: Option<&'a Congestion>,
+) -> impl Fn(&Coord) -> Option<Cost> + 'a {
+    move |coord| match (cost_at(coord)?, congestion) {
+        (Cost::Passable(cost), Some(congestion)) => {
+            Some(Cost::Passable(cost.saturating_add(congestion.get(coord))))
+        }
+        (cost, _) => Some(cost),
+    }
+}
+
+/// Counts the agents in every cell of grids with a [Congestion] layer whenever the layer is due
+/// for a refresh.
+fn update_congestion<T: Component>(
+    time: Res<Time>,
+    mut grids: Query<(Entity, &Grid, &Transform, &mut Congestion)>,
+    agents: Query<&Transform, With<T>>,
+) {
+    for (entity, grid, grid_transform, mut congestion) in grids.iter_mut() {
+        if !congestion
+            .bypass_change_detection()
+            .timer
+            .tick(time.delta())
+            .just_finished()
+        {
+            continue;
+        }
+
+        let mut occupancy = ChunkedField::<u16>::new();
+        for transform in agents.iter() {
+            let coord = grid.world_to_coord(&transform.translation, grid_transform);
+            if grid.within_bounds(&coord) {
+                occupancy[&coord] = occupancy[&coord].saturating_add(1);
+            }
+        }
+
+        // Only the cells of chunks holding agents now or at the last refresh may have changed.
+        let mut changed: Vec<Coord> = occupancy
+            .iter_coords()
+            .chain(congestion.occupancy.iter_coords())
+            .filter(|coord| grid.within_bounds(coord))
+            .filter(|coord| congestion.get(coord) != congestion.extra_cost(occupancy[coord]))
+            .collect();
+        changed.sort_unstable();
+        changed.dedup();
+
+        log::debug!("Congestion {:?} changed {} cell(s).", entity, changed.len());
+
+        if !changed.is_empty() {
+            congestion.occupancy = occupancy;
+            congestion.changed = changed;
+        } else {
+            congestion.bypass_change_detection().occupancy = occupancy;
+        }
+    }
+}
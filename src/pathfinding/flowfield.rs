@@ -1,5 +1,15 @@
-use std::{cmp::Reverse, collections::BinaryHeap};
-
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    time::{Duration, Instant},
+};
+
+use bevy::ecs::system::SystemParam;
+
+use super::cache::{cache_computed_flowfields, invalidate_flowfield_cache};
+use super::repair::repair_flowfield;
+use super::sliced::{reset_flowfield_budget, step_flowfield_progress};
+use super::task::poll_flowfield_tasks;
 use crate::prelude::*;
 
 pub struct FlowFieldPlugin;
@@ -7,21 +17,61 @@ pub struct FlowFieldPlugin;
 impl Plugin for FlowFieldPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<ComputeFlowField>();
+        app.add_event::<FlowFieldComputed>();
+        app.add_event::<FlowFieldCacheHit>();
+        app.add_event::<FlowFieldFailed>();
+        app.insert_resource(FlowFieldBudget::default());
+        app.init_resource::<FlowFieldBudgetLeft>();
+        app.init_resource::<TerrainCosts>();
+        app.add_system_to_stage(CoreStage::First, reset_flowfield_budget);
         app.add_system_set_to_stage(
             CoreStage::PreUpdate,
-            ConditionSet::new().with_system(compute_flowfield).into(),
+            ConditionSet::new()
+                .with_system(
+                    invalidate_flowfield_cache
+                        .after(SystemLabels::FlowFieldLayers)
+                        .before(compute_flowfield),
+                )
+                .with_system(compute_flowfield)
+                .with_system(repair_flowfield.after(compute_flowfield))
+                .with_system(poll_flowfield_tasks.after(repair_flowfield))
+                .with_system(step_flowfield_progress.after(repair_flowfield))
+                .with_system(
+                    cache_computed_flowfields
+                        .after(poll_flowfield_tasks)
+                        .after(step_flowfield_progress),
+                )
+                .into(),
         );
     }
 }
 
 /// The cost of a tile when calculating a flow field.
 #[cfg_attr(feature = "dev", derive(bevy_inspector_egui::Inspectable))]
-#[derive(Component, Debug, Deref, DerefMut, PartialEq, Eq, Clone, Copy)]
-pub struct Cost(pub u8);
+#[derive(Component, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Cost {
+    /// A passable tile with the given extra cost of stepping onto it.
+    Passable(u8),
+    /// An impassable tile, never integrated or flowed into.
+    Blocked,
+}
 
 impl Cost {
-    pub const MAX: Self = Self(u8::MAX);
-    pub const EMPTY: Self = Self(0);
+    pub const MAX: Self = Self::Passable(u8::MAX);
+    pub const EMPTY: Self = Self::Passable(0);
+
+    /// Returns the extra cost of stepping onto the tile, or `None` if it's blocked.
+    pub fn passable(self) -> Option<u8> {
+        match self {
+            Cost::Passable(cost) => Some(cost),
+            Cost::Blocked => None,
+        }
+    }
+
+    /// Returns true if the tile is impassable.
+    pub fn is_blocked(&self) -> bool {
+        *self == Cost::Blocked
+    }
 }
 
 impl Default for Cost {
@@ -30,20 +80,252 @@ impl Default for Cost {
     }
 }
 
-/// A flow field component. Stores the goal of the flow field & the time it was last updated.
-#[derive(Component, Default, Debug)]
+/// Which diagonal steps are allowed when integrating a flow field.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum DiagonalMovement {
+    /// Diagonal steps are always allowed.
+    Always,
+    /// Diagonal steps are only allowed if both orthogonal cells next to the step are passable.
+    #[default]
+    NoCornerCutting,
+    /// Only orthogonal steps are allowed.
+    Never,
+}
+
+impl DiagonalMovement {
+    /// Returns true if a step from `from` in direction `dir` is allowed.
+    pub fn allows(&self, from: &Coord, dir: Coord, passable: impl Fn(&Coord) -> bool) -> bool {
+        if dir.x == 0 || dir.y == 0 {
+            return true;
+        }
+
+        match self {
+            DiagonalMovement::Always => true,
+            DiagonalMovement::NoCornerCutting => {
+                passable(&(*from + Coord::new(dir.x, 0)))
+                    && passable(&(*from + Coord::new(0, dir.y)))
+            }
+            DiagonalMovement::Never => false,
+        }
+    }
+}
+
+/// Links a flow field living on its own entity to the grid it's computed over, so several flow
+/// fields can share one grid. Flow fields without it are computed over the grid of their entity.
+#[derive(Component, Debug, PartialEq, Eq, Clone, Copy)]
+pub struct FlowFieldGrid(pub Entity);
+
+impl FlowFieldGrid {
+    /// Returns the grid entity of the flow field on `entity`.
+    pub fn of(entity: Entity, grid: Option<&FlowFieldGrid>) -> Entity {
+        grid.map_or(entity, |grid| grid.0)
+    }
+}
+
+/// The grids flow fields are computed over, with their threat, congestion & the costs & terrain
+/// of their cells, from cell entities or [CellData], & the clearance of the flow fields.
+#[derive(SystemParam)]
+pub struct FlowFieldGrids<'w, 's> {
+    links: Query<'w, 's, &'static FlowFieldGrid>,
+    grids: Query<'w, 's, (&'static Grid, Option<&'static ThreatField>)>,
+    pub costs: Query<'w, 's, &'static Cost>,
+    pub terrains: Query<'w, 's, &'static Terrain>,
+    pub cells: Query<'w, 's, &'static CellData>,
+    pub terrain_costs: Res<'w, TerrainCosts>,
+    pub clearances: Query<'w, 's, &'static Clearance>,
+    pub congestions: Query<'w, 's, &'static Congestion>,
+}
+
+impl<'w, 's> FlowFieldGrids<'w, 's> {
+    /// Returns the grid entity of the flow field on `entity`.
+    pub fn grid_entity(&self, entity: Entity) -> Entity {
+        FlowFieldGrid::of(entity, self.links.get(entity).ok())
+    }
+
+    /// Returns the grid & threat of the flow field on `entity`, if any.
+    pub fn get(&self, entity: Entity) -> Option<(&Grid, Option<&ThreatField>)> {
+        self.grids.get(self.grid_entity(entity)).ok()
+    }
+}
+
+impl<'a> FlowFieldGrids<'a, 'a> {
+    /// Returns the grid of the flow field on `entity` & the cost of its cells as seen by the
+    /// flow field, with its movement class, clearance, congestion & threat weight applied.
+    pub fn cost_at(
+        &'a self,
+        entity: Entity,
+        flowfield: &FlowField,
+    ) -> Option<(&'a Grid, impl Fn(&Coord) -> Option<Cost> + 'a)> {
+        let (grid, cost_at) = self.terrain_cost_at(entity, flowfield.movement)?;
+        let (_, threat) = self.get(entity)?;
+        let clearance = self.clearances.get(entity).ok();
+        let congestion = self.congestions.get(self.grid_entity(entity)).ok();
+
+        let cost_at = with_clearance(cost_at, clearance, flowfield.min_clearance);
+        let cost_at = with_congestion(cost_at, congestion);
+        Some((grid, with_threat(cost_at, threat, flowfield.threat_weight)))
+    }
+
+    /// Returns the grid of the flow field on `entity` & the cost of its cells as seen by a
+    /// movement class, the [Terrain] of cells is ignored without one.
+    pub fn terrain_cost_at(
+        &'a self,
+        entity: Entity,
+        movement: Option<MovementClass>,
+    ) -> Option<(&'a Grid, impl Fn(&Coord) -> Option<Cost> + 'a)> {
+        let (grid, _) = self.get(entity)?;
+        let cells = self.cells.get(self.grid_entity(entity)).ok();
+
+        let cost_at = move |coord: &Coord| {
+            if let Some(cells) = cells {
+                let cost = cells.cost(coord)?;
+                return match movement {
+                    Some(class) => Some(self.terrain_costs.cell_cost(
+                        class,
+                        cells.terrain()[coord],
+                        cost,
+                    )),
+                    None => Some(cost),
+                };
+            }
+
+            let cell = grid.get(coord)?;
+            let cost = *self.costs.get(cell).ok()?;
+            match (movement, self.terrains.get(cell)) {
+                (Some(class), Ok(terrain)) => {
+                    Some(self.terrain_costs.cell_cost(class, *terrain, cost))
+                }
+                _ => Some(cost),
+            }
+        };
+
+        Some((grid, cost_at))
+    }
+}
+
+/// How a grid computes its flow field when a [ComputeFlowField] event is received.
+/// Grids without this component compute immediately.
+#[derive(Component, Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum FlowFieldMode {
+    /// Compute the flow field in the same frame, stalling it until done.
+    #[default]
+    Immediate,
+    /// Compute the flow field on the [AsyncComputeTaskPool] against a snapshot of the grid
+    /// costs. The current flow field is kept until the new one is swapped in.
+    Async,
+    /// Compute the flow field over several frames within the [FlowFieldBudget], against a
+    /// snapshot of the grid costs. The current flow field is kept until the new one is swapped in.
+    Sliced,
+}
+
+/// A goal cell of a flow field, integration starts from `cost` at `coord`.
+#[cfg_attr(feature = "dev", derive(bevy_inspector_egui::Inspectable))]
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct Goal {
+    pub coord: Coord,
+    pub cost: i32,
+}
+
+impl Goal {
+    /// Creates a new goal with the given starting cost.
+    pub fn new(coord: Coord, cost: i32) -> Self {
+        Self { coord, cost }
+    }
+}
+
+impl From<Coord> for Goal {
+    fn from(coord: Coord) -> Self {
+        Self::new(coord, ZERO_INTEGRATION)
+    }
+}
+
+/// A flow field component. Stores the goals of the flow field & the time it was last updated.
+/// Every cell flows towards the goal it can reach the cheapest.
+#[derive(Component, Default, Debug, Clone)]
 pub struct FlowField {
-    pub goal: Option<Coord>,
+    pub goals: Vec<Goal>,
+    pub diagonal: DiagonalMovement,
+    /// Whether cells with an unobstructed straight line to a goal flow directly towards it.
+    pub line_of_sight: bool,
     pub flow: Field<Option<Vec2>>,
     pub integration: Field<Option<i32>>,
+    /// The goal every cell has a line of sight to, only computed with `line_of_sight` enabled.
+    pub visible: Field<Option<Coord>>,
+    /// How much the [ThreatField] of the grid adds to the cost of cells, 0 ignores threat.
+    pub threat_weight: f32,
+    /// The movement class the flow field is computed for, the [Terrain] of cells is ignored
+    /// without one.
+    pub movement: Option<MovementClass>,
+    /// The minimum [Clearance] of the cells the flow field routes through, so agents larger
+    /// than a cell only take corridors wide enough for them. Ignored without a [Clearance].
+    pub min_clearance: u8,
+    /// The topology of the grid, taken from the grid whenever the flow field is computed.
+    pub topology: Topology,
 }
 
 impl FlowField {
     pub fn new(width: usize, height: usize) -> Self {
         Self {
-            goal: None,
+            goals: vec![],
+            diagonal: default(),
+            line_of_sight: false,
             flow: Field::new(width, height, vec![None; width * height]),
             integration: Field::new(width, height, vec![None; width * height]),
+            visible: default(),
+            threat_weight: 0.0,
+            movement: None,
+            min_clearance: 0,
+            topology: default(),
+        }
+    }
+
+    /// Sets which diagonal steps are allowed.
+    pub fn with_diagonal(mut self, diagonal: DiagonalMovement) -> Self {
+        self.diagonal = diagonal;
+        self
+    }
+
+    /// Sets whether cells with a line of sight to a goal flow directly towards it.
+    pub fn with_line_of_sight(mut self, line_of_sight: bool) -> Self {
+        self.line_of_sight = line_of_sight;
+        self
+    }
+
+    /// Sets how much the threat of a cell adds to its cost.
+    pub fn with_threat_weight(mut self, threat_weight: f32) -> Self {
+        self.threat_weight = threat_weight;
+        self
+    }
+
+    /// Sets the movement class the flow field is computed for.
+    pub fn with_movement(mut self, movement: MovementClass) -> Self {
+        self.movement = Some(movement);
+        self
+    }
+
+    /// Sets the minimum clearance of the cells the flow field routes through.
+    pub fn with_min_clearance(mut self, min_clearance: u8) -> Self {
+        self.min_clearance = min_clearance;
+        self
+    }
+
+    /// Sets the topology of the grid the flow field is computed on.
+    pub fn with_topology(mut self, topology: Topology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    /// Returns an empty flow field with the same size & settings, without goals.
+    pub fn empty_copy(&self) -> Self {
+        let size = self.integration.size;
+        Self {
+            diagonal: self.diagonal,
+            line_of_sight: self.line_of_sight,
+            threat_weight: self.threat_weight,
+            movement: self.movement,
+            min_clearance: self.min_clearance,
+            topology: self.topology,
+            ..Self::new(size.width, size.height)
         }
     }
 
@@ -65,112 +347,551 @@ impl FlowField {
         self.flow.clear();
         self.integration.clear();
     }
-}
 
-/// Compute flow field event for a given goal.
-#[derive(Debug, Clone)]
-pub struct ComputeFlowField {
-    pub goal: Coord,
-    pub grid_entity: Entity,
-}
+    /// Resizes the flow field, keeping every cell within the new dimensions at its coordinate &
+    /// dropping the goals outside of them.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.flow.resize(width, height);
+        self.integration.resize(width, height);
+        self.visible.resize(width, height);
+        let integration = &self.integration;
+        self.goals
+            .retain(|goal| integration.within_bounds(&goal.coord));
+    }
 
-/// Consumes [ComputeFlowField] events and computes & updates the flow field for the given goal.
-fn compute_flowfield(
-    mut ev_compute: EventReader<ComputeFlowField>,
-    mut grids: Query<(&Grid, &mut FlowField)>,
-    costs: Query<&Cost>,
-) {
-    for ev in ev_compute.iter() {
-        use std::time::Instant;
+    /// Returns true if the given coordinate is one of the goals.
+    pub fn is_goal(&self, coord: &Coord) -> bool {
+        self.goals.iter().any(|goal| goal.coord == *coord)
+    }
 
+    /// Resets & computes the integration & flow fields from the goals.
+    pub fn compute(&mut self, cost_at: impl Fn(&Coord) -> Option<Cost>) -> FlowFieldStats {
         let now = Instant::now();
-        let goal = ev.goal;
 
-        let (grid, mut flowfield) = grids
-            .get_mut(ev.grid_entity)
-            .expect("Grid entity not found");
+        self.clear();
 
-        log::info!(
-            "Compute flowfield {:?} for goal: {:?}.",
-            ev.grid_entity,
-            goal
+        // Add the goals to the queue with their starting cost & compute the integration field.
+        let mut queue = IntegrationQueue::new();
+        self.seed_goals(&mut queue, &cost_at, |_| {});
+        let nodes_expanded = self.integrate(&mut queue, &cost_at, |_| {});
+
+        // Compute the flow field from the integration field.
+        self.finish(&cost_at);
+
+        self.stats(nodes_expanded, now.elapsed(), cost_at)
+    }
+
+    /// Computes the line of sight, if enabled, & the flow field from a complete integration field.
+    pub fn finish(&mut self, cost_at: impl Fn(&Coord) -> Option<Cost>) {
+        if self.uses_line_of_sight() {
+            self.update_line_of_sight(cost_at);
+        }
+        self.update_flow_all();
+    }
+
+    /// Returns true if line of sight is enabled & supported by the topology, square grids only.
+    pub fn uses_line_of_sight(&self) -> bool {
+        self.line_of_sight && self.topology == Topology::Square
+    }
+
+    /// Returns true if a step from `from` in direction `dir` is allowed. Diagonal movement rules
+    /// only apply to square grids, every hex step is allowed.
+    pub fn allows(&self, from: &Coord, dir: Coord, passable: impl Fn(&Coord) -> bool) -> bool {
+        self.topology == Topology::Hex || self.diagonal.allows(from, dir, passable)
+    }
+
+    /// Returns the integration cost of a step in direction `dir`, see [step_cost]. Every hex
+    /// step is as long as an orthogonal square step.
+    pub fn step_cost(&self, dir: Coord, cost: u8) -> i32 {
+        match self.topology {
+            Topology::Square => step_cost(dir, cost),
+            Topology::Hex => STRAIGHT_COST * (1 + cost as i32),
+        }
+    }
+
+    /// Finds the goal every cell has an unobstructed straight line to. A goal is only visible if
+    /// heading straight for it is as cheap as following the integration field, cells with extra
+    /// cost break the line of sight so agents keep routing around them.
+    pub fn update_line_of_sight(&mut self, cost_at: impl Fn(&Coord) -> Option<Cost>) {
+        let size = self.integration.size;
+        self.visible = Field::new(
+            size.width,
+            size.height,
+            vec![None; size.width * size.height],
         );
 
-        if !grid.within_bounds(&ev.goal) {
-            log::error!(
-                "Goal {:?} is not within bounds of grid, aborting ...",
-                ev.goal
-            );
-            continue;
+        for coord in self.integration.iter_coords() {
+            self.visible[&coord] = self.visible_goal(&coord, &cost_at);
+        }
+    }
+
+    /// Returns the goal a cell has a line of sight to, see [FlowField::update_line_of_sight].
+    pub fn visible_goal(
+        &self,
+        coord: &Coord,
+        cost_at: impl Fn(&Coord) -> Option<Cost>,
+    ) -> Option<Coord> {
+        let coord = *coord;
+        let integration = self.integration[&coord]?;
+
+        self.goals
+            .iter()
+            .filter(|goal| {
+                self.integration.within_bounds(&goal.coord)
+                    && self.integration[&goal.coord].is_some()
+                    && goal.cost + distance(coord, goal.coord, self.diagonal) <= integration
+                    && line_of_sight(coord, goal.coord, |c| {
+                        *c == coord || *c == goal.coord || cost_at(c) == Some(Cost::EMPTY)
+                    })
+            })
+            .min_by_key(|goal| goal.cost + distance(coord, goal.coord, self.diagonal))
+            .map(|goal| goal.coord)
+    }
+
+    /// Returns the statistics of the integration field.
+    pub fn stats(
+        &self,
+        nodes_expanded: usize,
+        elapsed: Duration,
+        cost_at: impl Fn(&Coord) -> Option<Cost>,
+    ) -> FlowFieldStats {
+        let mut stats = FlowFieldStats {
+            nodes_expanded,
+            elapsed,
+            ..default()
+        };
+
+        for coord in self.integration.iter_coords() {
+            if self.integration[&coord].is_some() {
+                stats.reachable += 1;
+            } else if cost_at(&coord).and_then(Cost::passable).is_some() {
+                stats.unreachable += 1;
+            }
         }
 
-        // Set the goal of the flow field.
-        flowfield.goal = Some(goal);
+        stats
+    }
 
-        // Reset the integration field
-        flowfield.clear();
+    /// Seeds every passable goal without an integration value with its starting cost. Blocked
+    /// goals can't be reached & are left without integration.
+    pub fn seed_goals(
+        &mut self,
+        queue: &mut IntegrationQueue,
+        cost_at: impl Fn(&Coord) -> Option<Cost>,
+        mut touched: impl FnMut(Coord),
+    ) {
+        for goal in self.goals.clone() {
+            if !self.integration.within_bounds(&goal.coord)
+                || cost_at(&goal.coord).and_then(Cost::passable).is_none()
+            {
+                continue;
+            }
 
-        // Compute the integration field.
-        let mut queue = BinaryHeap::new();
+            if goal.cost < self.integration[&goal.coord].unwrap_or(MAX_INTEGRATION) {
+                self.integration[&goal.coord] = Some(goal.cost);
+                queue.push(Reverse((goal.cost, goal.coord)));
+                touched(goal.coord);
+            }
+        }
+    }
 
-        const ZERO_COST: i32 = 0_i32;
-        const MAX_COST: i32 = i32::MAX;
+    /// Runs the integration from the coords in `queue` until it is exhausted, lowering the
+    /// integration value of every cell that can be reached cheaper. Calls `touched` for every
+    /// cell that got a new integration value. Returns the number of cells expanded.
+    pub fn integrate(
+        &mut self,
+        queue: &mut IntegrationQueue,
+        cost_at: impl Fn(&Coord) -> Option<Cost>,
+        mut touched: impl FnMut(Coord),
+    ) -> usize {
+        let mut nodes_expanded = 0;
+        while let Some(expanded) = self.integrate_next(queue, &cost_at, &mut touched) {
+            nodes_expanded += expanded as usize;
+        }
+        nodes_expanded
+    }
 
-        // Add the goal to the queue with a cost of 0.
-        flowfield.integration[&goal] = Some(ZERO_COST);
-        queue.push(Reverse((ZERO_COST, goal)));
+    /// Pops the cheapest coord in `queue` & expands it. Returns `None` if the queue is exhausted,
+    /// otherwise whether the coord was expanded or skipped as a stale entry.
+    pub fn integrate_next(
+        &mut self,
+        queue: &mut IntegrationQueue,
+        cost_at: impl Fn(&Coord) -> Option<Cost>,
+        mut touched: impl FnMut(Coord),
+    ) -> Option<bool> {
+        let (width, height) = (self.integration.size.width, self.integration.size.height);
+
+        let Reverse((cost, coord)) = queue.pop()?;
+
+        // Skip stale entries, the cell has been reached cheaper since it was queued.
+        if cost > self.integration[&coord].unwrap_or(MAX_INTEGRATION) {
+            return Some(false);
+        }
 
-        while let Some(Reverse((cost, coord))) = queue.pop() {
-            for neighbor in grid.data.neighbors8(&coord) {
-                let neighbor_entity = match grid.data[&neighbor] {
-                    Some(entity) => entity,
-                    None => continue,
-                };
+        for neighbor in self.topology.neighbors(&coord, width, height) {
+            let neighbor_cost = match cost_at(&neighbor).and_then(Cost::passable) {
+                Some(cost) => cost,
+                None => continue,
+            };
 
-                let neighbor_cost = match costs.get(neighbor_entity) {
-                    Ok(cost) => cost,
-                    Err(_) => continue,
-                };
+            let dir = neighbor - coord;
+            let passable = |c: &Coord| cost_at(c).and_then(Cost::passable).is_some();
+            if !self.allows(&coord, dir, passable) {
+                continue;
+            }
 
-                let cost = cost + neighbor_cost.0 as i32 + neighbor.distance(goal) as i32;
+            let cost = cost + self.step_cost(dir, neighbor_cost);
 
-                if cost < flowfield.integration[&neighbor].unwrap_or(MAX_COST) {
-                    flowfield.integration[&neighbor] = Some(cost);
-                    queue.push(Reverse((cost, neighbor)));
-                }
+            if cost < self.integration[&neighbor].unwrap_or(MAX_INTEGRATION) {
+                self.integration[&neighbor] = Some(cost);
+                queue.push(Reverse((cost, neighbor)));
+                touched(neighbor);
             }
         }
 
-        // Compute the flow field from the integration field.
+        Some(true)
+    }
 
-        for y in 0..grid.data.size.width {
-            for x in 0..grid.data.size.height {
-                let coord = Coord::new(x as i32, y as i32);
-                if flowfield.integration[&coord].is_none() {
-                    continue;
-                }
+    /// Recomputes the flow direction of a cell from the integration field.
+    /// Cells with a line of sight to a goal flow directly towards it.
+    pub fn update_flow(&mut self, coord: &Coord) {
+        if self.integration[coord].is_none() {
+            self.flow[coord] = None;
+            return;
+        }
+
+        if self.uses_line_of_sight() && self.visible.within_bounds(coord) {
+            if let Some(goal) = self.visible[coord] {
+                self.flow[coord] = Some(Vec2::from(goal - *coord).normalize_or_zero());
+                return;
+            }
+        }
 
-                let mut min_cost = i32::MAX;
-                let mut min_dir = Coord::default();
+        let mut min_cost = MAX_INTEGRATION;
+        let mut min_neighbor = None;
 
-                if coord == goal {
-                    flowfield.set(&coord, Some(min_dir.into()));
+        if !self.is_goal(coord) {
+            let size = self.integration.size;
+            let reachable = |c: &Coord| self.integration[c].is_some();
+            for neighbor in self.topology.neighbors(coord, size.width, size.height) {
+                if !self.allows(coord, neighbor - *coord, reachable) {
                     continue;
                 }
 
-                for neighbor in flowfield.integration.neighbors8(&coord) {
-                    if let Some(cost) = flowfield.integration[&neighbor] {
-                        if cost < min_cost {
-                            min_cost = cost;
-                            min_dir = neighbor - coord;
-                        }
+                if let Some(cost) = self.integration[&neighbor] {
+                    if cost < min_cost {
+                        min_cost = cost;
+                        min_neighbor = Some(neighbor);
                     }
                 }
+            }
+        }
+
+        self.flow[coord] = Some(match min_neighbor {
+            Some(neighbor) => self.topology.direction(coord, &neighbor),
+            None => Vec2::ZERO,
+        });
+    }
+
+    /// Recomputes the flow direction of every cell from the integration field.
+    pub fn update_flow_all(&mut self) {
+        for coord in self.flow.iter_coords().collect::<Vec<_>>() {
+            self.update_flow(&coord);
+        }
+    }
+}
+
+/// Priority queue of `(integration, coord)` pairs used when integrating a flow field.
+pub type IntegrationQueue = BinaryHeap<Reverse<(i32, Coord)>>;
+
+const ZERO_INTEGRATION: i32 = 0_i32;
+const MAX_INTEGRATION: i32 = i32::MAX;
+
+/// Integration cost of an orthogonal step onto an empty cell.
+pub const STRAIGHT_COST: i32 = 10;
+/// Integration cost of a diagonal step onto an empty cell, `STRAIGHT_COST * √2` rounded.
+pub const DIAGONAL_COST: i32 = 14;
+
+/// Returns the integration cost of a step in direction `dir` onto a passable cell with the given
+/// extra cost. The length of the step is scaled by the cost of the cell, so diagonal steps stay
+/// proportionally more expensive on costly terrain.
+#[inline]
+pub fn step_cost(dir: Coord, cost: u8) -> i32 {
+    let length = if dir.x != 0 && dir.y != 0 {
+        DIAGONAL_COST
+    } else {
+        STRAIGHT_COST
+    };
+    length * (1 + cost as i32)
+}
+
+/// Compute flow field event for the given goals.
+#[derive(Debug, Clone)]
+pub struct ComputeFlowField {
+    pub goals: Vec<Goal>,
+    /// The entity holding the [FlowField], the grid itself unless it has a [FlowFieldGrid].
+    pub grid_entity: Entity,
+}
+
+impl ComputeFlowField {
+    /// Creates a compute event for a single goal.
+    pub fn new(goal: Coord, grid_entity: Entity) -> Self {
+        Self {
+            goals: vec![goal.into()],
+            grid_entity,
+        }
+    }
+}
+
+/// Statistics of a flow field computation.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FlowFieldStats {
+    /// The number of cells expanded by the integration.
+    pub nodes_expanded: usize,
+    /// The number of cells that reach a goal.
+    pub reachable: usize,
+    /// The number of passable cells that can't reach any goal.
+    pub unreachable: usize,
+    /// The time spent computing the flow field.
+    pub elapsed: Duration,
+}
+
+/// Sent when the flow field of a grid has been computed & swapped in.
+#[derive(Debug, Clone)]
+pub struct FlowFieldComputed {
+    pub grid_entity: Entity,
+    pub goals: Vec<Goal>,
+    pub stats: FlowFieldStats,
+}
 
-                flowfield.set(&coord, Some(min_dir.into()));
+/// Sent instead of [FlowFieldComputed] when the goals of a [ComputeFlowField] request are
+/// handed out by the [FlowFieldCache] of the grid, the [FlowField] of the grid is left as it is.
+#[derive(Debug, Clone)]
+pub struct FlowFieldCacheHit {
+    pub grid_entity: Entity,
+    pub goals: Vec<Goal>,
+    /// The statistics of the cached flow field when it was computed.
+    pub stats: FlowFieldStats,
+}
+
+/// Sent when a [ComputeFlowField] request couldn't be computed.
+#[derive(Debug, Clone)]
+pub struct FlowFieldFailed {
+    pub grid_entity: Entity,
+    pub goals: Vec<Goal>,
+    pub error: FlowFieldError,
+}
+
+/// Why a flow field couldn't be computed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowFieldError {
+    /// The entity has no [FlowField] or its grid has no [Grid].
+    GridNotFound,
+    /// None of the goals are within bounds of the grid.
+    NoGoalWithinBounds,
+    /// The topology of the grid isn't supported, hierarchical flow fields need a square grid.
+    UnsupportedTopology,
+}
+
+/// Consumes [ComputeFlowField] events and computes & updates the flow field for the given goals.
+pub fn compute_flowfield(
+    mut commands: Commands,
+    mut ev_compute: EventReader<ComputeFlowField>,
+    mut ev_computed: EventWriter<FlowFieldComputed>,
+    mut ev_cache_hit: EventWriter<FlowFieldCacheHit>,
+    mut ev_failed: EventWriter<FlowFieldFailed>,
+    mut flowfields: Query<(
+        &mut FlowField,
+        Option<&FlowFieldMode>,
+        Option<&FlowFieldCache>,
+    )>,
+    grids: FlowFieldGrids,
+) {
+    // Coalesce the requests, only the last one per grid is computed.
+    let mut requests: Vec<&ComputeFlowField> = vec![];
+    for ev in ev_compute.iter() {
+        requests.retain(|request| request.grid_entity != ev.grid_entity);
+        requests.push(ev);
+    }
+
+    for ev in requests {
+        let found = flowfields.get_mut(ev.grid_entity).ok().and_then(|found| {
+            let (grid, cost_at) = grids.cost_at(ev.grid_entity, &found.0)?;
+            Some((found, grid, cost_at))
+        });
+        let ((mut flowfield, mode, cache), grid, cost_at) = match found {
+            Some(found) => found,
+            None => {
+                log::error!("Grid entity {:?} not found.", ev.grid_entity);
+                ev_failed.send(FlowFieldFailed {
+                    grid_entity: ev.grid_entity,
+                    goals: ev.goals.clone(),
+                    error: FlowFieldError::GridNotFound,
+                });
+                continue;
+            }
+        };
+
+        log::info!(
+            "Compute flowfield {:?} for goals: {:?}.",
+            ev.grid_entity,
+            ev.goals
+        );
+
+        if flowfield.topology != grid.topology {
+            flowfield.topology = grid.topology;
+        }
+
+        let mut goals = ev.goals.clone();
+        goals.retain(|goal| {
+            let within_bounds = grid.within_bounds(&goal.coord);
+            if !within_bounds {
+                log::error!("Goal {:?} is not within bounds of grid.", goal.coord);
+            } else if cost_at(&goal.coord).and_then(Cost::passable).is_none() {
+                log::warn!("Goal {:?} is blocked, no cell will reach it.", goal.coord);
+            }
+            within_bounds
+        });
+
+        if goals.is_empty() {
+            log::error!("No goal is within bounds of grid, aborting ...");
+            ev_failed.send(FlowFieldFailed {
+                grid_entity: ev.grid_entity,
+                goals: ev.goals.clone(),
+                error: FlowFieldError::NoGoalWithinBounds,
+            });
+            continue;
+        }
+
+        // Goals cached for the same costs are handed out by the cache, cancel any computation
+        // still running for this grid.
+        if let Some(cached) = cache.and_then(|cache| cache.get(&goals)) {
+            log::info!("Reuse cached flowfield {:?}.", ev.grid_entity);
+
+            commands
+                .entity(ev.grid_entity)
+                .remove::<FlowFieldTask>()
+                .remove::<FlowFieldProgress>();
+
+            ev_cache_hit.send(FlowFieldCacheHit {
+                grid_entity: ev.grid_entity,
+                goals,
+                stats: cached.stats,
+            });
+            continue;
+        }
+
+        // Other modes replace & cancel any computation still running for this grid.
+        match mode.copied().unwrap_or_default() {
+            FlowFieldMode::Immediate => {}
+            FlowFieldMode::Async => {
+                let mut next = flowfield.empty_copy();
+                next.goals = goals;
+                let task = FlowFieldTask::spawn(next, cost_snapshot(grid.data.size, cost_at));
+                commands.entity(ev.grid_entity).insert(task);
+                continue;
             }
+            FlowFieldMode::Sliced => {
+                let mut next = flowfield.empty_copy();
+                next.goals = goals;
+                let progress = FlowFieldProgress::new(next, cost_snapshot(grid.data.size, cost_at));
+                commands.entity(ev.grid_entity).insert(progress);
+                continue;
+            }
+        }
+
+        // Set the goals of the flow field & compute it.
+        flowfield.goals = goals.clone();
+        let stats = flowfield.compute(cost_at);
+
+        log::info!("Compute took: {:.2?}.", stats.elapsed);
+
+        ev_computed.send(FlowFieldComputed {
+            grid_entity: ev.grid_entity,
+            goals,
+            stats,
+        });
+    }
+}
+
+/// Returns a snapshot of the cost of every cell of a grid of the given size.
+pub fn cost_snapshot(
+    size: FieldSize,
+    cost_at: impl Fn(&Coord) -> Option<Cost>,
+) -> Field<Option<Cost>> {
+    let data = iter_coords(size.width, size.height)
+        .map(|coord| cost_at(&coord))
+        .collect();
+    Field::new(size.width, size.height, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn integrate(costs: &Field<Cost>, diagonal: DiagonalMovement) -> FlowField {
+        let size = costs.size;
+        let mut flowfield = FlowField::new(size.width, size.height).with_diagonal(diagonal);
+        flowfield.goals = vec![Coord::new(0, 0).into()];
+        flowfield.compute(|coord| costs.within_bounds(coord).then(|| costs[coord]));
+        flowfield
+    }
+
+    #[test]
+    fn octile_distances() {
+        let mut costs = Field::new(10, 10, vec![Cost::EMPTY; 100]);
+        let flowfield = integrate(&costs, DiagonalMovement::NoCornerCutting);
+        for coord in flowfield.integration.iter_coords() {
+            let (min, max) = (coord.x.min(coord.y), coord.x.max(coord.y));
+            let distance = DIAGONAL_COST * min + STRAIGHT_COST * (max - min);
+            assert_eq!(flowfield.integration[&coord], Some(distance));
         }
 
-        log::info!("Compute took: {:.2?}.", now.elapsed());
+        // Stepping diagonally past a blocked cell cuts its corner.
+        costs[&Coord::new(1, 0)] = Cost::Blocked;
+        let flowfield = integrate(&costs, DiagonalMovement::NoCornerCutting);
+        assert_eq!(flowfield.integration[&Coord::new(1, 0)], None);
+        assert_eq!(flowfield.integration[&Coord::new(1, 1)], Some(20));
+        let flowfield = integrate(&costs, DiagonalMovement::Always);
+        assert_eq!(flowfield.integration[&Coord::new(1, 1)], Some(14));
+        let flowfield = integrate(&costs, DiagonalMovement::Never);
+        assert_eq!(flowfield.integration[&Coord::new(1, 1)], Some(20));
+        assert_eq!(flowfield.integration[&Coord::new(3, 3)], Some(60));
+    }
+
+    #[test]
+    fn repair_matches_compute() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(7);
+        for line_of_sight in [false, true] {
+            let mut costs = Field::new(16, 16, vec![Cost::EMPTY; 256]);
+            let mut flowfield = FlowField::new(16, 16).with_line_of_sight(line_of_sight);
+            flowfield.goals = vec![Coord::new(2, 3).into(), Coord::new(12, 13).into()];
+            flowfield.compute(|coord| costs.within_bounds(coord).then(|| costs[coord]));
+
+            for _ in 0..200 {
+                // Block & unblock a cell & some of its neighbors, so diagonal steps past them
+                // become (dis)allowed.
+                let origin = Coord::new(rng.gen_range(0..16), rng.gen_range(0..16));
+                let mut changed = vec![origin];
+                changed.extend(costs.neighbors8(&origin).filter(|_| rng.gen_bool(0.2)));
+                for coord in changed.iter() {
+                    costs[coord] = match rng.gen_range(0..3) {
+                        0 => Cost::Blocked,
+                        1 => Cost::EMPTY,
+                        _ => Cost::Passable(rng.gen_range(1..20)),
+                    };
+                }
+                let cost_at = |coord: &Coord| costs.within_bounds(coord).then(|| costs[coord]);
+                flowfield.repair(&changed, cost_at);
+
+                let mut full = flowfield.empty_copy();
+                full.goals = flowfield.goals.clone();
+                full.compute(cost_at);
+                assert_eq!(flowfield.integration.data, full.integration.data);
+                assert_eq!(flowfield.flow.data, full.flow.data);
+            }
+        }
     }
 }
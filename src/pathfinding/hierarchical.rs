@@ -0,0 +1,813 @@
+use std::{cmp::Reverse, time::Instant};
+
+use bevy::utils::{HashMap, HashSet};
+
+use super::sliced::step_flowfield_progress;
+use crate::prelude::*;
+
+pub struct HierarchicalFlowFieldPlugin;
+
+impl Plugin for HierarchicalFlowFieldPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ComputeHierarchicalFlowField>();
+        app.add_event::<RequestSectorRoute>();
+        app.add_system_set_to_stage(
+            CoreStage::PreUpdate,
+            ConditionSet::new()
+                .with_system(update_sector_graph.before(step_flowfield_progress))
+                .with_system(compute_hierarchical_flowfield.after(update_sector_graph))
+                .with_system(build_sector_routes.after(compute_hierarchical_flowfield))
+                .into(),
+        );
+    }
+}
+
+/// Splits a [Grid] into fixed size sectors connected by portals. Lives on the grid entity and is
+/// kept up to date as cell costs change. The paths within the sectors are built over several
+/// frames within the [FlowFieldBudget]. Square grids only, graphs on hex grids are never built.
+#[derive(Component, Debug, Default, Clone)]
+pub struct SectorGraph {
+    pub sector_size: usize,
+    /// The size of the grid the graph was built for, in cells.
+    pub size: FieldSize,
+    pub sectors: Field<Sector>,
+    /// The sectors whose portals or paths changed with the last update, every sector once the
+    /// graph has been built.
+    pub changed: Vec<Coord>,
+    /// The sectors whose paths are still to be built.
+    pending: Vec<Coord>,
+}
+
+/// A sector of a [SectorGraph].
+#[derive(Debug, Default, Clone)]
+pub struct Sector {
+    /// Portals leaving the sector.
+    pub portals: Vec<Portal>,
+    /// The unique inside cells of the portals in sorted order, the nodes of the graph.
+    pub nodes: Vec<Coord>,
+    /// Integration cost between each pair of nodes within the sector, indexed `[from * len + to]`.
+    pub paths: Vec<Option<i32>>,
+}
+
+/// A passable crossing from a cell inside a sector to an adjacent cell in a neighbor sector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Portal {
+    pub inside: Coord,
+    pub outside: Coord,
+}
+
+impl SectorGraph {
+    /// Creates a new, unbuilt sector graph with the given sector size.
+    pub fn new(sector_size: usize) -> Self {
+        Self {
+            sector_size: sector_size.max(1),
+            ..default()
+        }
+    }
+
+    /// Returns true if the graph has been built for a grid of the given size.
+    pub fn is_built_for(&self, size: &FieldSize) -> bool {
+        self.is_sized_for(size) && !self.is_building()
+    }
+
+    /// Returns true if the graph has been, or is being, built for a grid of the given size.
+    pub fn is_sized_for(&self, size: &FieldSize) -> bool {
+        self.size == *size && !self.sectors.data.is_empty()
+    }
+
+    /// Returns true if the paths of some sectors are still to be built.
+    pub fn is_building(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Returns the sector coordinate of the given cell.
+    pub fn sector_of(&self, coord: &Coord) -> Coord {
+        let size = self.sector_size as i32;
+        Coord::new(coord.x.div_euclid(size), coord.y.div_euclid(size))
+    }
+
+    /// Returns the origin cell & the width and height of the given sector.
+    pub fn sector_bounds(&self, sector: &Coord) -> (Coord, usize, usize) {
+        let origin = *sector * self.sector_size as i32;
+        let width = self.sector_size.min(self.size.width - origin.x as usize);
+        let height = self.sector_size.min(self.size.height - origin.y as usize);
+        (origin, width, height)
+    }
+
+    /// Returns true if the given cell lies within the given sector.
+    pub fn in_sector(&self, sector: &Coord, coord: &Coord) -> bool {
+        self.within_bounds(coord) && self.sector_of(coord) == *sector
+    }
+
+    /// Returns true if the given cell is within the grid dimensions.
+    pub fn within_bounds(&self, coord: &Coord) -> bool {
+        coord.x >= 0
+            && coord.y >= 0
+            && coord.x < self.size.width as i32
+            && coord.y < self.size.height as i32
+    }
+
+    /// Builds the whole graph for a grid of the given size at once.
+    pub fn build(&mut self, size: FieldSize, cost_at: impl Fn(&Coord) -> Option<Cost>) {
+        self.start_build(size, &cost_at);
+        self.build_step(&mut FlowFieldBudgetLeft::default(), &cost_at);
+    }
+
+    /// Starts building the graph for a grid of the given size. The portals are placed right away,
+    /// the paths within the sectors are built by [SectorGraph::build_step].
+    pub fn start_build(&mut self, size: FieldSize, cost_at: impl Fn(&Coord) -> Option<Cost>) {
+        let sectors = FieldSize {
+            width: size.width.div_ceil(self.sector_size),
+            height: size.height.div_ceil(self.sector_size),
+        };
+
+        self.size = size;
+        self.sectors = Field::new(
+            sectors.width,
+            sectors.height,
+            vec![default(); sectors.width * sectors.height],
+        );
+
+        for sector in self.sectors.iter_coords().collect::<Vec<_>>() {
+            for dir in [Coord::new(1, 0), Coord::new(0, 1)] {
+                self.build_border(&sector, dir, &cost_at);
+            }
+        }
+
+        self.pending = self.sectors.iter_coords().collect();
+        self.changed.clear();
+    }
+
+    /// Builds the paths of pending sectors until the budget is spent, at least one sector per
+    /// call. Returns true once the graph is built.
+    pub fn build_step(
+        &mut self,
+        budget: &mut FlowFieldBudgetLeft,
+        cost_at: impl Fn(&Coord) -> Option<Cost>,
+    ) -> bool {
+        if !self.is_building() {
+            return true;
+        }
+
+        let started = Instant::now();
+        let mut nodes = 0;
+        while let Some(sector) = self.pending.pop() {
+            nodes += self.build_paths(&sector, &cost_at);
+            if nodes >= budget.nodes || budget.time.is_some_and(|max| started.elapsed() >= max) {
+                break;
+            }
+        }
+        budget.spend(nodes, started.elapsed());
+
+        if self.is_building() {
+            return false;
+        }
+        self.changed = self.sectors.iter_coords().collect();
+        true
+    }
+
+    /// Rebuilds the sectors containing the given cells, along with the borders & paths of their
+    /// neighbors.
+    pub fn rebuild(&mut self, changed: &[Coord], cost_at: impl Fn(&Coord) -> Option<Cost>) {
+        let dirty: HashSet<Coord> = changed
+            .iter()
+            .filter(|coord| self.within_bounds(coord))
+            .map(|coord| self.sector_of(coord))
+            .collect();
+
+        let mut paths = HashSet::default();
+        for sector in dirty.iter() {
+            for dir in NEIGHBORS {
+                self.build_border(sector, dir, &cost_at);
+            }
+            paths.insert(*sector);
+            paths.extend(self.sectors.neighbors(sector));
+        }
+
+        for sector in paths.iter() {
+            self.build_paths(sector, &cost_at);
+        }
+        self.pending.retain(|sector| !paths.contains(sector));
+        self.changed = paths.into_iter().collect();
+    }
+
+    /// Returns the nodes reachable from a node in one step, within its sector or across one of
+    /// its portals, with the integration cost of reaching them from `cost` at the node.
+    pub fn edges(
+        &self,
+        node: &Coord,
+        cost: i32,
+        cost_at: impl Fn(&Coord) -> Option<Cost>,
+    ) -> Vec<(Coord, i32)> {
+        let sector = &self.sectors[&self.sector_of(node)];
+        let from = match sector.nodes.binary_search(node) {
+            Ok(from) => from,
+            Err(_) => return vec![],
+        };
+
+        let within = sector.nodes.iter().enumerate().filter_map(|(to, other)| {
+            sector.paths[from * sector.nodes.len() + to].map(|path| (*other, cost + path))
+        });
+
+        let across = sector
+            .portals
+            .iter()
+            .filter(|portal| portal.inside == *node)
+            .filter_map(|portal| {
+                cost_at(&portal.outside)
+                    .and_then(Cost::passable)
+                    .map(|outside| {
+                        let dir = portal.outside - portal.inside;
+                        (portal.outside, cost + step_cost(dir, outside))
+                    })
+            });
+
+        within.chain(across).collect()
+    }
+
+    /// Recomputes the portals on the border between `sector` and its neighbor in direction `dir`.
+    fn build_border(
+        &mut self,
+        sector: &Coord,
+        dir: Coord,
+        cost_at: impl Fn(&Coord) -> Option<Cost>,
+    ) {
+        let neighbor = *sector + dir;
+        if !self.sectors.within_bounds(&neighbor) {
+            return;
+        }
+
+        self.sectors[sector]
+            .portals
+            .retain(|portal| portal.outside - portal.inside != dir);
+        self.sectors[&neighbor]
+            .portals
+            .retain(|portal| portal.inside - portal.outside != dir);
+
+        // Split the border into runs of open crossings & place a portal in the middle of each.
+        let mut runs: Vec<Vec<Coord>> = vec![];
+        let mut run = vec![];
+        for inside in self.border_cells(sector, dir) {
+            let outside = inside + dir;
+            let passable = |c: &Coord| cost_at(c).and_then(Cost::passable).is_some();
+            if passable(&inside) && passable(&outside) {
+                run.push(inside);
+            } else if !run.is_empty() {
+                runs.push(std::mem::take(&mut run));
+            }
+        }
+        if !run.is_empty() {
+            runs.push(run);
+        }
+
+        for run in runs {
+            let inside = run[run.len() / 2];
+            let outside = inside + dir;
+            self.sectors[sector]
+                .portals
+                .push(Portal { inside, outside });
+            self.sectors[&neighbor].portals.push(Portal {
+                inside: outside,
+                outside: inside,
+            });
+        }
+    }
+
+    /// Returns the cells of a sector on the side facing direction `dir`.
+    fn border_cells(&self, sector: &Coord, dir: Coord) -> Vec<Coord> {
+        let (origin, width, height) = self.sector_bounds(sector);
+        let (max_x, max_y) = (origin.x + width as i32 - 1, origin.y + height as i32 - 1);
+        match (dir.x, dir.y) {
+            (1, 0) => (origin.y..=max_y).map(|y| Coord::new(max_x, y)).collect(),
+            (-1, 0) => (origin.y..=max_y)
+                .map(|y| Coord::new(origin.x, y))
+                .collect(),
+            (0, 1) => (origin.x..=max_x).map(|x| Coord::new(x, max_y)).collect(),
+            (0, -1) => (origin.x..=max_x)
+                .map(|x| Coord::new(x, origin.y))
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Recomputes the nodes of a sector & the integration cost between each pair of them.
+    /// Returns the number of cells expanded.
+    fn build_paths(&mut self, sector: &Coord, cost_at: impl Fn(&Coord) -> Option<Cost>) -> usize {
+        let mut nodes: Vec<Coord> = self.sectors[sector]
+            .portals
+            .iter()
+            .map(|portal| portal.inside)
+            .collect();
+        nodes.sort_unstable();
+        nodes.dedup();
+
+        let mut paths = vec![None; nodes.len() * nodes.len()];
+        let mut expanded = 0;
+        for (from, node) in nodes.iter().enumerate() {
+            let integration = self.integrate_sector(sector, [(*node, 0)], &cost_at);
+            expanded += integration.iter().filter(|value| value.is_some()).count();
+            let offset = self.sector_offset(sector);
+            for (to, other) in nodes.iter().enumerate() {
+                paths[from * nodes.len() + to] = integration[&(*other - offset)];
+            }
+        }
+
+        let sector = &mut self.sectors[sector];
+        sector.nodes = nodes;
+        sector.paths = paths;
+        expanded
+    }
+
+    /// Returns the offset of a sector's local integration field, which has a one cell ring around
+    /// the sector so that flow can point into neighbor sectors.
+    pub fn sector_offset(&self, sector: &Coord) -> Coord {
+        *sector * self.sector_size as i32 - Coord::new(1, 1)
+    }
+
+    /// Integrates a sector from the given `(cell, cost)` seeds. Only cells within the sector are
+    /// expanded, seeds may lie on the ring around it. Returns the local integration field.
+    pub fn integrate_sector(
+        &self,
+        sector: &Coord,
+        seeds: impl IntoIterator<Item = (Coord, i32)>,
+        cost_at: impl Fn(&Coord) -> Option<Cost>,
+    ) -> Field<Option<i32>> {
+        let (_, width, height) = self.sector_bounds(sector);
+        let offset = self.sector_offset(sector);
+        let (width, height) = (width + 2, height + 2);
+        let mut integration = Field::new(width, height, vec![None; width * height]);
+
+        let mut queue = IntegrationQueue::new();
+        for (coord, cost) in seeds {
+            let local = coord - offset;
+            if integration.within_bounds(&local) && cost < integration[&local].unwrap_or(i32::MAX) {
+                integration[&local] = Some(cost);
+                queue.push(Reverse((cost, local)));
+            }
+        }
+
+        while let Some(Reverse((cost, local))) = queue.pop() {
+            if cost > integration[&local].unwrap_or(i32::MAX) {
+                continue;
+            }
+
+            for neighbor in neighbors8(&local, width, height) {
+                let coord = neighbor + offset;
+                if !self.in_sector(sector, &coord) {
+                    continue;
+                }
+
+                let neighbor_cost = match cost_at(&coord).and_then(Cost::passable) {
+                    Some(cost) => cost,
+                    None => continue,
+                };
+
+                // Portals only connect sectors orthogonally, so diagonal steps may not cut
+                // corners & cells outside the sector count as blocked corners. Otherwise cells
+                // would be reached over steps the portal graph can't route.
+                let dir = neighbor - local;
+                let passable = |c: &Coord| {
+                    self.in_sector(sector, c) && cost_at(c).and_then(Cost::passable).is_some()
+                };
+                if !DiagonalMovement::NoCornerCutting.allows(&(local + offset), dir, passable) {
+                    continue;
+                }
+
+                let cost = cost + step_cost(dir, neighbor_cost);
+                if cost < integration[&neighbor].unwrap_or(i32::MAX) {
+                    integration[&neighbor] = Some(cost);
+                    queue.push(Reverse((cost, neighbor)));
+                }
+            }
+        }
+
+        integration
+    }
+}
+
+/// A flow field over a [SectorGraph]. The goals are resolved over the portal graph and local
+/// flow fields are only built for the sectors that are on a requested route.
+#[derive(Component, Debug)]
+pub struct HierarchicalFlowField {
+    pub grid_entity: Entity,
+    pub goals: Vec<Goal>,
+    /// Integration value of every reachable portal node.
+    pub portals: HashMap<Coord, i32>,
+    /// Local flow fields of the built sectors, keyed by sector coordinate.
+    pub sectors: HashMap<Coord, FlowField>,
+    /// Cells whose route was requested before the sector graph was built, routed once it is.
+    pub requested: Vec<Coord>,
+}
+
+impl HierarchicalFlowField {
+    pub fn new(grid_entity: Entity) -> Self {
+        Self {
+            grid_entity,
+            goals: vec![],
+            portals: HashMap::default(),
+            sectors: HashMap::default(),
+            requested: vec![],
+        }
+    }
+
+    /// Returns the flow direction of a cell, if its sector has been built.
+    pub fn get(&self, graph: &SectorGraph, coord: &Coord) -> Option<Vec2> {
+        let sector = graph.sector_of(coord);
+        self.sectors
+            .get(&sector)
+            .and_then(|field| field.get(&(*coord - graph.sector_offset(&sector))))
+    }
+
+    /// Returns true if the given sector has a local flow field.
+    pub fn is_built(&self, sector: &Coord) -> bool {
+        self.sectors.contains_key(sector)
+    }
+
+    /// Returns the passable goals within the given sector. Blocked goals can't be reached.
+    fn sector_goals(
+        &self,
+        graph: &SectorGraph,
+        sector: &Coord,
+        cost_at: impl Fn(&Coord) -> Option<Cost>,
+    ) -> Vec<Goal> {
+        self.goals
+            .iter()
+            .filter(|goal| {
+                graph.in_sector(sector, &goal.coord)
+                    && cost_at(&goal.coord).and_then(Cost::passable).is_some()
+            })
+            .copied()
+            .collect()
+    }
+
+    /// Resolves the goals over the portal graph & rebuilds every built sector.
+    pub fn compute(&mut self, graph: &SectorGraph, cost_at: impl Fn(&Coord) -> Option<Cost>) {
+        self.portals.clear();
+
+        let mut queue = IntegrationQueue::new();
+        let mut touched = vec![];
+        self.seed_goal_sectors(graph, &mut queue, &cost_at, &mut touched);
+        self.propagate(graph, queue, &cost_at, &mut touched);
+
+        self.rebuild_sectors(graph, |_| true, &cost_at);
+    }
+
+    /// Repairs the portal values after the given sectors of the graph changed. Only the nodes
+    /// derived through the changed sectors are resolved again, & only the built sectors whose
+    /// seeds changed are rebuilt.
+    pub fn repair(
+        &mut self,
+        graph: &SectorGraph,
+        changed: &[Coord],
+        cost_at: impl Fn(&Coord) -> Option<Cost>,
+    ) {
+        let changed: HashSet<Coord> = changed.iter().copied().collect();
+
+        // Invalidate the nodes of the changed sectors & every node whose value depends on them.
+        let mut stack: Vec<(Coord, i32)> = self
+            .portals
+            .iter()
+            .filter(|(node, _)| changed.contains(&graph.sector_of(node)))
+            .map(|(node, cost)| (*node, *cost))
+            .collect();
+        let mut touched: Vec<Coord> = stack.iter().map(|(node, _)| *node).collect();
+        for node in touched.iter() {
+            self.portals.remove(node);
+        }
+
+        while let Some((node, cost)) = stack.pop() {
+            if !graph.within_bounds(&node) {
+                continue;
+            }
+            for (other, cost) in graph.edges(&node, cost, &cost_at) {
+                if self.portals.get(&other) == Some(&cost) {
+                    self.portals.remove(&other);
+                    stack.push((other, cost));
+                    touched.push(other);
+                }
+            }
+        }
+
+        // Resume from the goals & the valid nodes around the invalidated ones.
+        let mut queue = IntegrationQueue::new();
+        self.seed_goal_sectors(graph, &mut queue, &cost_at, &mut touched);
+
+        let mut border: HashSet<Coord> = HashSet::default();
+        for node in touched.iter().filter(|node| graph.within_bounds(node)) {
+            let sector = graph.sector_of(node);
+            border.insert(sector);
+            border.extend(graph.sectors.neighbors(&sector));
+        }
+        for sector in border {
+            for node in graph.sectors[&sector].nodes.iter() {
+                if let Some(cost) = self.portals.get(node) {
+                    queue.push(Reverse((*cost, *node)));
+                }
+            }
+        }
+
+        self.propagate(graph, queue, &cost_at, &mut touched);
+
+        let touched: HashSet<Coord> = touched.into_iter().collect();
+        self.rebuild_sectors(
+            graph,
+            |sector| {
+                changed.contains(sector)
+                    || graph.sectors[sector]
+                        .portals
+                        .iter()
+                        .any(|portal| touched.contains(&portal.outside))
+            },
+            &cost_at,
+        );
+    }
+
+    /// Seeds the nodes of every goal sector with their local cost to the goals, where it's
+    /// cheaper than their current value.
+    fn seed_goal_sectors(
+        &mut self,
+        graph: &SectorGraph,
+        queue: &mut IntegrationQueue,
+        cost_at: impl Fn(&Coord) -> Option<Cost>,
+        touched: &mut Vec<Coord>,
+    ) {
+        let mut goal_sectors: Vec<Coord> = self
+            .goals
+            .iter()
+            .filter(|goal| graph.within_bounds(&goal.coord))
+            .map(|goal| graph.sector_of(&goal.coord))
+            .collect();
+        goal_sectors.sort_unstable();
+        goal_sectors.dedup();
+
+        for sector in goal_sectors.iter() {
+            let seeds = self
+                .sector_goals(graph, sector, &cost_at)
+                .into_iter()
+                .map(|goal| (goal.coord, goal.cost));
+            let integration = graph.integrate_sector(sector, seeds, &cost_at);
+            let offset = graph.sector_offset(sector);
+            for node in graph.sectors[sector].nodes.iter() {
+                if let Some(cost) = integration[&(*node - offset)] {
+                    if cost < self.portals.get(node).copied().unwrap_or(i32::MAX) {
+                        self.portals.insert(*node, cost);
+                        queue.push(Reverse((cost, *node)));
+                        touched.push(*node);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Runs the integration over the portal graph until `queue` is exhausted, lowering the value
+    /// of every node that can be reached cheaper.
+    fn propagate(
+        &mut self,
+        graph: &SectorGraph,
+        mut queue: IntegrationQueue,
+        cost_at: impl Fn(&Coord) -> Option<Cost>,
+        touched: &mut Vec<Coord>,
+    ) {
+        while let Some(Reverse((cost, node))) = queue.pop() {
+            if cost > self.portals.get(&node).copied().unwrap_or(i32::MAX) {
+                continue;
+            }
+
+            for (other, cost) in graph.edges(&node, cost, &cost_at) {
+                if cost < self.portals.get(&other).copied().unwrap_or(i32::MAX) {
+                    self.portals.insert(other, cost);
+                    queue.push(Reverse((cost, other)));
+                    touched.push(other);
+                }
+            }
+        }
+    }
+
+    /// Rebuilds the local flow fields of the built sectors that need it, dropping the sectors
+    /// that are no longer part of the graph.
+    fn rebuild_sectors(
+        &mut self,
+        graph: &SectorGraph,
+        needs_rebuild: impl Fn(&Coord) -> bool,
+        cost_at: impl Fn(&Coord) -> Option<Cost>,
+    ) {
+        let built: Vec<Coord> = self.sectors.keys().copied().collect();
+        for sector in built {
+            if !graph.sectors.within_bounds(&sector) {
+                self.sectors.remove(&sector);
+            } else if needs_rebuild(&sector) {
+                self.build_sector(graph, &sector, &cost_at);
+            }
+        }
+    }
+
+    /// Builds the local flow field of a sector, seeded from its goals & the portals leaving it.
+    pub fn build_sector(
+        &mut self,
+        graph: &SectorGraph,
+        sector: &Coord,
+        cost_at: impl Fn(&Coord) -> Option<Cost>,
+    ) {
+        let offset = graph.sector_offset(sector);
+        let goals = self.sector_goals(graph, sector, &cost_at);
+
+        let seeds = goals.iter().map(|goal| (goal.coord, goal.cost)).chain(
+            graph.sectors[sector].portals.iter().filter_map(|portal| {
+                self.portals
+                    .get(&portal.outside)
+                    .map(|cost| (portal.outside, *cost))
+            }),
+        );
+
+        let integration = graph.integrate_sector(sector, seeds, cost_at);
+        let mut field = FlowField {
+            goals: goals
+                .iter()
+                .map(|goal| Goal::new(goal.coord - offset, goal.cost))
+                .collect(),
+            diagonal: DiagonalMovement::NoCornerCutting,
+            flow: Field::new(
+                integration.size.width,
+                integration.size.height,
+                vec![None; integration.data.len()],
+            ),
+            integration,
+            ..default()
+        };
+        field.update_flow_all();
+
+        self.sectors.insert(*sector, field);
+    }
+
+    /// Builds the local flow fields of every sector on the route from the given cell to the
+    /// closest goal.
+    pub fn build_route(
+        &mut self,
+        graph: &SectorGraph,
+        from: &Coord,
+        cost_at: impl Fn(&Coord) -> Option<Cost>,
+    ) {
+        if self.goals.is_empty() || !graph.within_bounds(from) {
+            return;
+        }
+
+        let mut coord = *from;
+        for _ in 0..graph.sectors.data.len() {
+            let sector = graph.sector_of(&coord);
+            if !self.is_built(&sector) {
+                self.build_sector(graph, &sector, &cost_at);
+            }
+
+            // Follow the flow until it leaves the sector.
+            let field = &self.sectors[&sector];
+            let offset = graph.sector_offset(&sector);
+            let mut local = coord - offset;
+            let mut steps = field.flow.data.len();
+            while graph.in_sector(&sector, &(local + offset)) && steps > 0 {
+                match field.flow[&local] {
+                    Some(dir) if dir != Vec2::ZERO => local = local + Coord::from(dir),
+                    _ => return,
+                }
+                steps -= 1;
+            }
+
+            if steps == 0 || !graph.within_bounds(&(local + offset)) {
+                return;
+            }
+
+            coord = local + offset;
+        }
+    }
+}
+
+/// Compute event for a [HierarchicalFlowField] with the given goals.
+#[derive(Debug, Clone)]
+pub struct ComputeHierarchicalFlowField {
+    pub goals: Vec<Goal>,
+    pub flowfield_entity: Entity,
+}
+
+/// Requests the local flow fields along the route from a cell to the closest goal of a
+/// [HierarchicalFlowField] to be built.
+#[derive(Debug, Clone)]
+pub struct RequestSectorRoute {
+    pub from: Coord,
+    pub flowfield_entity: Entity,
+}
+
+/// Builds sector graphs for new or resized grids over several frames within what is left of the
+/// [FlowFieldBudget] & rebuilds the sectors where any cell changed.
+fn update_sector_graph(
+    mut budget: ResMut<FlowFieldBudgetLeft>,
+    mut grids: Query<(Entity, &Grid, Option<&CellData>, &mut SectorGraph)>,
+    changed: ChangedCells,
+    costs: Query<&Cost>,
+) {
+    let changed_by_grid = changed.by_grid();
+
+    for (entity, grid, cells, mut graph) in grids.iter_mut() {
+        if grid.topology != Topology::Square {
+            continue;
+        }
+
+        let cost_at = |coord: &Coord| cell_cost(grid, cells, &costs, coord);
+        if !graph.is_sized_for(&grid.data.size) {
+            graph.start_build(grid.data.size, cost_at);
+            log::info!("Sector graph {:?} build started.", entity);
+        } else if let Some(changed) = changed_by_grid.get(&entity) {
+            graph.rebuild(changed, cost_at);
+        }
+
+        if graph.is_building() && !budget.is_spent() && graph.build_step(&mut budget, cost_at) {
+            log::info!("Sector graph {:?} built.", entity);
+        }
+    }
+}
+
+/// Consumes [ComputeHierarchicalFlowField] events & repairs hierarchical flow fields whenever
+/// their sector graph changed. Flow fields wait for their graph to be built, requests for hex
+/// grids fail with [FlowFieldError::UnsupportedTopology].
+fn compute_hierarchical_flowfield(
+    mut ev_compute: EventReader<ComputeHierarchicalFlowField>,
+    mut ev_failed: EventWriter<FlowFieldFailed>,
+    mut flowfields: Query<(Entity, &mut HierarchicalFlowField)>,
+    grids: Query<(
+        &Grid,
+        Option<&CellData>,
+        ChangeTrackers<SectorGraph>,
+        &SectorGraph,
+    )>,
+    costs: Query<&Cost>,
+) {
+    let mut dirty = HashSet::default();
+    for ev in ev_compute.iter() {
+        if let Ok((_, mut flowfield)) = flowfields.get_mut(ev.flowfield_entity) {
+            let grid_entity = flowfield.grid_entity;
+            if grids
+                .get(grid_entity)
+                .is_ok_and(|(grid, ..)| grid.topology != Topology::Square)
+            {
+                log::error!("Grid {:?} isn't square, aborting ...", grid_entity);
+                ev_failed.send(FlowFieldFailed {
+                    grid_entity,
+                    goals: ev.goals.clone(),
+                    error: FlowFieldError::UnsupportedTopology,
+                });
+                continue;
+            }
+
+            flowfield.goals = ev.goals.clone();
+            dirty.insert(ev.flowfield_entity);
+        }
+    }
+
+    for (entity, mut flowfield) in flowfields.iter_mut() {
+        let (grid, cells, tracker, graph) = match grids.get(flowfield.grid_entity) {
+            Ok(grid) => grid,
+            Err(_) => continue,
+        };
+
+        if !graph.is_built_for(&grid.data.size) {
+            continue;
+        }
+
+        let cost_at = |coord: &Coord| cell_cost(grid, cells, &costs, coord);
+        if dirty.contains(&entity) {
+            flowfield.compute(graph, cost_at);
+        } else if tracker.is_changed() {
+            flowfield.repair(graph, &graph.changed, cost_at);
+        }
+
+        for from in std::mem::take(&mut flowfield.requested) {
+            flowfield.build_route(graph, &from, cost_at);
+        }
+    }
+}
+
+/// Consumes [RequestSectorRoute] events & builds the sectors along the requested routes.
+fn build_sector_routes(
+    mut ev_route: EventReader<RequestSectorRoute>,
+    mut flowfields: Query<&mut HierarchicalFlowField>,
+    grids: Query<(&Grid, Option<&CellData>, &SectorGraph)>,
+    costs: Query<&Cost>,
+) {
+    for ev in ev_route.iter() {
+        let mut flowfield = match flowfields.get_mut(ev.flowfield_entity) {
+            Ok(flowfield) => flowfield,
+            Err(_) => continue,
+        };
+
+        if let Ok((grid, cells, graph)) = grids.get(flowfield.grid_entity) {
+            if grid.topology != Topology::Square {
+                continue;
+            }
+            if !graph.is_built_for(&grid.data.size) {
+                flowfield.requested.push(ev.from);
+                continue;
+            }
+
+            let cost_at = |coord: &Coord| cell_cost(grid, cells, &costs, coord);
+            flowfield.build_route(graph, &ev.from, cost_at);
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use super::path::{check_ends, reconstruct_path};
+use crate::prelude::*;
+
+/// Heuristic weight of the A* search used when Jump Point Search can't be used.
+pub const JPS_FALLBACK_WEIGHT: f32 = 1.5;
+
+/// Finds the cheapest path from `start` to `goal` with Jump Point Search, including both ends.
+///
+/// Jump Point Search only expands the cells where the path may turn, which requires every
+/// passable cell to have the same cost & diagonal steps without corner cutting. `uniform` is the
+/// cost shared by every passable cell as returned by [uniform_cost], callers answering many
+/// queries on the same grid should keep it around instead of scanning the grid for every query.
+/// Without it, or with other diagonal movement, this falls back to weighted A*.
+pub fn jump_point_search(
+    size: FieldSize,
+    start: Coord,
+    goal: Coord,
+    diagonal: DiagonalMovement,
+    uniform: Option<u8>,
+    cost_at: impl Fn(&Coord) -> Option<Cost>,
+) -> Result<Vec<Coord>, PathError> {
+    let (width, height) = (size.width, size.height);
+    let mut scores: Field<Option<i32>> = Field::new(width, height, vec![None; width * height]);
+    let mut came_from: Field<Option<Coord>> = Field::new(width, height, vec![None; width * height]);
+
+    check_ends(&scores, start, goal, &cost_at)?;
+
+    let cost = match uniform {
+        Some(cost) if diagonal == DiagonalMovement::NoCornerCutting => cost,
+        _ => {
+            log::debug!("Costs aren't uniform, falling back to weighted A*.");
+            return weighted_astar(
+                size,
+                start,
+                goal,
+                diagonal,
+                Topology::Square,
+                JPS_FALLBACK_WEIGHT,
+                cost_at,
+            );
+        }
+    };
+
+    let jumper = Jumper {
+        goal,
+        walkable: |coord: &Coord| {
+            coord.x >= 0
+                && coord.y >= 0
+                && coord.x < width as i32
+                && coord.y < height as i32
+                && cost_at(coord).and_then(Cost::passable).is_some()
+        },
+    };
+    let heuristic = |coord: &Coord| distance(*coord, goal, diagonal) * (1 + cost as i32);
+
+    let mut open = BinaryHeap::new();
+    scores[&start] = Some(0);
+    open.push(Reverse((heuristic(&start), start)));
+
+    while let Some(Reverse((estimate, coord))) = open.pop() {
+        if coord == goal {
+            return Ok(expand_jumps(&reconstruct_path(&came_from, goal)));
+        }
+
+        let score = scores[&coord].unwrap_or(i32::MAX);
+
+        // Skip stale entries, the cell has been reached cheaper since it was queued.
+        if estimate > score + heuristic(&coord) {
+            continue;
+        }
+
+        for dir in jumper.directions(coord, came_from[&coord]) {
+            let jump_point = match jumper.jump(coord + dir, dir) {
+                Some(jump_point) => jump_point,
+                None => continue,
+            };
+
+            let delta = jump_point - coord;
+            let steps = delta.x.abs().max(delta.y.abs());
+            let score = score + steps * step_cost(dir, cost);
+            if score < scores[&jump_point].unwrap_or(i32::MAX) {
+                scores[&jump_point] = Some(score);
+                came_from[&jump_point] = Some(coord);
+                open.push(Reverse((score + heuristic(&jump_point), jump_point)));
+            }
+        }
+    }
+
+    Err(PathError::Unreachable)
+}
+
+/// Returns the cost shared by every passable cell, `None` if the costs differ.
+pub fn uniform_cost(size: FieldSize, cost_at: impl Fn(&Coord) -> Option<Cost>) -> Option<u8> {
+    let mut uniform = None;
+    for coord in iter_coords(size.width, size.height) {
+        if let Some(cost) = cost_at(&coord).and_then(Cost::passable) {
+            match uniform {
+                None => uniform = Some(cost),
+                Some(uniform) if uniform != cost => return None,
+                _ => {}
+            }
+        }
+    }
+    // A grid without passable cells is trivially uniform.
+    Some(uniform.unwrap_or_default())
+}
+
+/// Fills in the cells between consecutive jump points, which always lie on a straight or
+/// diagonal line.
+fn expand_jumps(jump_points: &[Coord]) -> Vec<Coord> {
+    let mut path = vec![];
+    for pair in jump_points.windows(2) {
+        let (from, to) = (pair[0], pair[1]);
+        let dir = Coord::new((to.x - from.x).signum(), (to.y - from.y).signum());
+        let mut coord = from;
+        while coord != to {
+            path.push(coord);
+            coord = coord + dir;
+        }
+    }
+    path.extend(jump_points.last());
+    path
+}
+
+/// Jumps along straight & diagonal lines without corner cutting until a cell where the path
+/// may turn is found.
+struct Jumper<F> {
+    goal: Coord,
+    walkable: F,
+}
+
+impl<F: Fn(&Coord) -> bool> Jumper<F> {
+    /// Returns the pruned directions to search from a cell reached from `parent`.
+    fn directions(&self, coord: Coord, parent: Option<Coord>) -> Vec<Coord> {
+        let walkable = |dir: Coord| (self.walkable)(&(coord + dir));
+
+        let parent = match parent {
+            Some(parent) => parent,
+            None => {
+                return NEIGHBORS_8
+                    .iter()
+                    .copied()
+                    .filter(|&dir| {
+                        walkable(dir)
+                            && (dir.x == 0
+                                || dir.y == 0
+                                || walkable(Coord::new(dir.x, 0)) && walkable(Coord::new(0, dir.y)))
+                    })
+                    .collect();
+            }
+        };
+
+        let (dx, dy) = ((coord.x - parent.x).signum(), (coord.y - parent.y).signum());
+        let mut dirs = vec![];
+
+        if dx != 0 && dy != 0 {
+            let (horizontal, vertical) = (walkable(Coord::new(dx, 0)), walkable(Coord::new(0, dy)));
+            if vertical {
+                dirs.push(Coord::new(0, dy));
+            }
+            if horizontal {
+                dirs.push(Coord::new(dx, 0));
+            }
+            if horizontal && vertical {
+                dirs.push(Coord::new(dx, dy));
+            }
+        } else if dx != 0 {
+            let (up, down) = (walkable(Coord::new(0, 1)), walkable(Coord::new(0, -1)));
+            if walkable(Coord::new(dx, 0)) {
+                dirs.push(Coord::new(dx, 0));
+                if up {
+                    dirs.push(Coord::new(dx, 1));
+                }
+                if down {
+                    dirs.push(Coord::new(dx, -1));
+                }
+            }
+            if up {
+                dirs.push(Coord::new(0, 1));
+            }
+            if down {
+                dirs.push(Coord::new(0, -1));
+            }
+        } else {
+            let (right, left) = (walkable(Coord::new(1, 0)), walkable(Coord::new(-1, 0)));
+            if walkable(Coord::new(0, dy)) {
+                dirs.push(Coord::new(0, dy));
+                if right {
+                    dirs.push(Coord::new(1, dy));
+                }
+                if left {
+                    dirs.push(Coord::new(-1, dy));
+                }
+            }
+            if right {
+                dirs.push(Coord::new(1, 0));
+            }
+            if left {
+                dirs.push(Coord::new(-1, 0));
+            }
+        }
+
+        dirs
+    }
+
+    /// Jumps from `coord` in direction `dir`, returns the first jump point found.
+    fn jump(&self, mut coord: Coord, dir: Coord) -> Option<Coord> {
+        let walkable = |c: Coord| (self.walkable)(&c);
+
+        loop {
+            if !walkable(coord) {
+                return None;
+            }
+            if coord == self.goal {
+                return Some(coord);
+            }
+
+            if dir.x != 0 && dir.y != 0 {
+                // Diagonal jumps stop where a straight jump finds a jump point.
+                let horizontal = Coord::new(dir.x, 0);
+                let vertical = Coord::new(0, dir.y);
+                if self.jump(coord + horizontal, horizontal).is_some()
+                    || self.jump(coord + vertical, vertical).is_some()
+                {
+                    return Some(coord);
+                }
+            } else if dir.x != 0 {
+                // Straight jumps stop next to the end of a wall running alongside them.
+                if (walkable(coord + Coord::new(0, -1))
+                    && !walkable(coord + Coord::new(-dir.x, -1)))
+                    || (walkable(coord + Coord::new(0, 1))
+                        && !walkable(coord + Coord::new(-dir.x, 1)))
+                {
+                    return Some(coord);
+                }
+            } else if (walkable(coord + Coord::new(-1, 0))
+                && !walkable(coord + Coord::new(-1, -dir.y)))
+                || (walkable(coord + Coord::new(1, 0)) && !walkable(coord + Coord::new(1, -dir.y)))
+            {
+                return Some(coord);
+            }
+
+            // Diagonal steps need both orthogonal cells to be passable.
+            if !walkable(coord + Coord::new(dir.x, 0)) || !walkable(coord + Coord::new(0, dir.y)) {
+                return None;
+            }
+
+            coord = coord + dir;
+        }
+    }
+}
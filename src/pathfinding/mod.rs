@@ -1,12 +1,49 @@
+mod cache;
+mod cells;
+mod clearance;
+mod compact;
+mod congestion;
 mod flowfield;
+mod hierarchical;
+mod jps;
+mod movement;
+mod path;
+mod placement;
+mod regions;
+mod repair;
+mod sample;
+mod sliced;
+mod task;
+mod threat;
+pub use self::cache::*;
+pub use self::cells::*;
+pub use self::clearance::*;
+pub use self::compact::*;
+pub use self::congestion::*;
 pub use self::flowfield::*;
+pub use self::hierarchical::*;
+pub use self::jps::*;
+pub use self::movement::*;
+pub use self::path::*;
+pub use self::placement::*;
+pub use self::regions::*;
+pub use self::repair::*;
+pub use self::sliced::*;
+pub use self::task::*;
+pub use self::threat::*;
 use crate::prelude::*;
 
 pub struct PathfindingPlugin;
 
 impl Plugin for PathfindingPlugin {
     fn build(&self, app: &mut App) {
+        app.add_plugin(CellDataPlugin);
+        app.add_plugin(ClearancePlugin);
         app.add_plugin(FlowFieldPlugin);
+        app.add_plugin(HierarchicalFlowFieldPlugin);
+        app.add_plugin(PathPlugin);
+        app.add_plugin(RegionsPlugin);
+        app.add_plugin(ThreatPlugin);
         app.add_system_set(ConditionSet::new().run_in_state(AppState::InGame).into());
         #[cfg(feature = "dev")]
         app.add_system_set(
@@ -22,19 +59,19 @@ impl Plugin for PathfindingPlugin {
 #[cfg(feature = "dev")]
 fn debug_grid(
     mut grids: Query<(&Grid, &Transform, Option<&DebugColor>)>,
-    cells: Query<(&Coord, &Parent, &Cost)>,
+    cells: Query<(&Coord, &Parent, &Cost, Option<&Terrain>)>,
     mut lines: ResMut<DebugLines>,
 ) {
-    for (coord, parent, cost) in cells.iter() {
+    for (coord, parent, cost, terrain) in cells.iter() {
         let (grid, grid_transform, debug_color) = grids.get_mut(parent.get()).unwrap();
 
-        let color = if *cost == Cost::MAX {
-            Color::RED
-        } else {
-            match debug_color {
+        let color = match (cost, terrain) {
+            (Cost::Blocked, _) | (_, Some(Terrain::Wall)) => Color::RED,
+            (_, Some(Terrain::Water)) => Color::BLUE,
+            _ => match debug_color {
                 Some(DebugColor(color)) => *color,
                 None => Color::WHITE,
-            }
+            },
         };
 
         let translation = grid.coord_to_world(&coord, grid_transform);
@@ -0,0 +1,99 @@
+use bevy::utils::HashMap;
+
+use crate::prelude::*;
+
+/// The terrain of a cell, its cost depends on the [MovementClass] of the flow field.
+#[cfg_attr(feature = "dev", derive(bevy_inspector_egui::Inspectable))]
+#[derive(Component, Debug, Default, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Terrain {
+    #[default]
+    Ground,
+    Wall,
+    Water,
+    Mud,
+}
+
+/// How a unit moves across terrain, every class has its own table in [TerrainCosts].
+#[cfg_attr(feature = "dev", derive(bevy_inspector_egui::Inspectable))]
+#[derive(Component, Debug, Default, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum MovementClass {
+    #[default]
+    Ground,
+    Flying,
+    Heavy,
+}
+
+/// Query filter for cells whose [Cost] or [Terrain] changed.
+pub type CellCostChanged = Or<(Changed<Cost>, Changed<Terrain>)>;
+
+/// The cost of every [Terrain] per [MovementClass]. Terrain missing from a table is empty.
+///
+/// The terrain cost is added on top of the [Cost] of the cell, which is shared by every class:
+/// a cell with [Cost::Blocked], e.g. a building, blocks every class, including
+/// [MovementClass::Flying]. Obstacles only some classes pass, like walls & water, are terrain
+/// on cells with [Cost::EMPTY].
+#[derive(Resource, Debug, Clone)]
+pub struct TerrainCosts {
+    tables: HashMap<MovementClass, HashMap<Terrain, Cost>>,
+}
+
+impl Default for TerrainCosts {
+    fn default() -> Self {
+        Self { tables: default() }
+            .with(MovementClass::Ground, Terrain::Wall, Cost::Blocked)
+            .with(MovementClass::Ground, Terrain::Water, Cost::Blocked)
+            .with(MovementClass::Ground, Terrain::Mud, Cost::Passable(4))
+            .with(MovementClass::Heavy, Terrain::Wall, Cost::Blocked)
+            .with(MovementClass::Heavy, Terrain::Water, Cost::Blocked)
+            .with(MovementClass::Heavy, Terrain::Mud, Cost::Passable(16))
+    }
+}
+
+impl TerrainCosts {
+    /// Sets the cost of a terrain for a movement class.
+    pub fn with(mut self, class: MovementClass, terrain: Terrain, cost: Cost) -> Self {
+        self.set(class, terrain, cost);
+        self
+    }
+
+    /// Sets the cost of a terrain for a movement class.
+    pub fn set(&mut self, class: MovementClass, terrain: Terrain, cost: Cost) {
+        self.tables.entry(class).or_default().insert(terrain, cost);
+    }
+
+    /// Returns the cost of a terrain for a movement class.
+    pub fn get(&self, class: MovementClass, terrain: Terrain) -> Cost {
+        self.tables
+            .get(&class)
+            .and_then(|table| table.get(&terrain))
+            .copied()
+            .unwrap_or(Cost::EMPTY)
+    }
+
+    /// Returns the cost of a cell with the given terrain & [Cost] for a movement class.
+    pub fn cell_cost(&self, class: MovementClass, terrain: Terrain, cost: Cost) -> Cost {
+        match (self.get(class, terrain), cost) {
+            (Cost::Passable(terrain), Cost::Passable(cost)) => {
+                Cost::Passable(terrain.saturating_add(cost))
+            }
+            _ => Cost::Blocked,
+        }
+    }
+}
+
+/// The flow fields of a grid per [MovementClass], so agents can pick the field of their class.
+/// Every flow field lives on its own entity with a [FlowFieldGrid] linking it to the grid.
+#[derive(Component, Debug, Default, Clone)]
+pub struct ClassFlowFields(pub HashMap<MovementClass, Entity>);
+
+impl ClassFlowFields {
+    /// Returns the flow field entity of a movement class, if any.
+    pub fn get(&self, class: MovementClass) -> Option<Entity> {
+        self.0.get(&class).copied()
+    }
+
+    /// Returns the flow field entity of every movement class.
+    pub fn iter(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.0.values().copied()
+    }
+}
@@ -0,0 +1,431 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use bevy::utils::HashMap;
+
+use crate::prelude::*;
+
+pub struct PathPlugin;
+
+impl Plugin for PathPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<RequestPath>();
+        app.add_event::<PathComputed>();
+        app.add_system_set_to_stage(
+            CoreStage::PreUpdate,
+            ConditionSet::new().with_system(compute_paths).into(),
+        );
+    }
+}
+
+/// A single path query between two cells of a grid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathQuery {
+    pub start: Coord,
+    pub goal: Coord,
+    /// Which diagonal steps are allowed, [DiagonalMovement::Never] gives 4-connectivity.
+    pub diagonal: DiagonalMovement,
+    /// Whether the path is smoothed by string-pulling, square grids only.
+    pub smooth: bool,
+    pub algorithm: PathAlgorithm,
+    /// The topology of the grid, set from the grid by [RequestPath].
+    pub topology: Topology,
+}
+
+/// The search algorithm used by a [PathQuery].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PathAlgorithm {
+    /// A* over every cell.
+    #[default]
+    AStar,
+    /// Jump Point Search, much faster on uniform cost terrain. Falls back to weighted A* when
+    /// the costs aren't uniform, corner cutting rules other than
+    /// [DiagonalMovement::NoCornerCutting] are used or on hex grids.
+    JumpPoint,
+}
+
+impl PathQuery {
+    /// Creates a new 8-connected, smoothed path query.
+    pub fn new(start: Coord, goal: Coord) -> Self {
+        Self {
+            start,
+            goal,
+            diagonal: default(),
+            smooth: true,
+            algorithm: default(),
+            topology: default(),
+        }
+    }
+
+    /// Sets which diagonal steps are allowed.
+    pub fn with_diagonal(mut self, diagonal: DiagonalMovement) -> Self {
+        self.diagonal = diagonal;
+        self
+    }
+
+    /// Sets whether the path is smoothed by string-pulling.
+    pub fn with_smoothing(mut self, smooth: bool) -> Self {
+        self.smooth = smooth;
+        self
+    }
+
+    /// Sets the search algorithm.
+    pub fn with_algorithm(mut self, algorithm: PathAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Sets the topology of the grid.
+    pub fn with_topology(mut self, topology: Topology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    /// Finds a path from start to goal on a grid of the given size, including both ends.
+    pub fn find(
+        &self,
+        size: FieldSize,
+        cost_at: impl Fn(&Coord) -> Option<Cost>,
+    ) -> Result<Vec<Coord>, PathError> {
+        let uniform = match (self.algorithm, self.topology) {
+            (PathAlgorithm::JumpPoint, Topology::Square) => uniform_cost(size, &cost_at),
+            _ => None,
+        };
+        self.find_with_uniform_cost(size, uniform, cost_at)
+    }
+
+    /// Finds a path like [PathQuery::find], with the cost shared by every passable cell already
+    /// known, see [uniform_cost]. Only used by [PathAlgorithm::JumpPoint].
+    pub fn find_with_uniform_cost(
+        &self,
+        size: FieldSize,
+        uniform: Option<u8>,
+        cost_at: impl Fn(&Coord) -> Option<Cost>,
+    ) -> Result<Vec<Coord>, PathError> {
+        let (start, goal, diagonal) = (self.start, self.goal, self.diagonal);
+        let path = match (self.algorithm, self.topology) {
+            (PathAlgorithm::AStar, topology) => {
+                astar(size, start, goal, diagonal, topology, &cost_at)?
+            }
+            (PathAlgorithm::JumpPoint, Topology::Square) => {
+                jump_point_search(size, start, goal, diagonal, uniform, &cost_at)?
+            }
+            (PathAlgorithm::JumpPoint, Topology::Hex) => weighted_astar(
+                size,
+                start,
+                goal,
+                diagonal,
+                Topology::Hex,
+                JPS_FALLBACK_WEIGHT,
+                &cost_at,
+            )?,
+        };
+        if self.smooth && self.topology == Topology::Square {
+            Ok(smooth_path(&path, cost_at))
+        } else {
+            Ok(path)
+        }
+    }
+}
+
+/// Why no path could be found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathError {
+    /// The grid entity has no [Grid].
+    GridNotFound,
+    /// The start is not within bounds of the grid.
+    StartOutOfBounds,
+    /// The goal is not within bounds of the grid.
+    GoalOutOfBounds,
+    /// The start cell is blocked.
+    StartBlocked,
+    /// The goal cell is blocked.
+    GoalBlocked,
+    /// No path connects the start & goal.
+    Unreachable,
+}
+
+/// Finds the cheapest path from `start` to `goal` with A*, including both ends.
+/// Steps cost the same as when integrating a flow field.
+pub fn astar(
+    size: FieldSize,
+    start: Coord,
+    goal: Coord,
+    diagonal: DiagonalMovement,
+    topology: Topology,
+    cost_at: impl Fn(&Coord) -> Option<Cost>,
+) -> Result<Vec<Coord>, PathError> {
+    weighted_astar(size, start, goal, diagonal, topology, 1.0, cost_at)
+}
+
+/// Finds a path from `start` to `goal` with A*, the heuristic is scaled by `weight`.
+/// Weights above 1 expand fewer cells, but the path may be up to `weight` times too costly.
+pub fn weighted_astar(
+    size: FieldSize,
+    start: Coord,
+    goal: Coord,
+    diagonal: DiagonalMovement,
+    topology: Topology,
+    weight: f32,
+    cost_at: impl Fn(&Coord) -> Option<Cost>,
+) -> Result<Vec<Coord>, PathError> {
+    let (width, height) = (size.width, size.height);
+    let mut scores: Field<Option<i32>> = Field::new(width, height, vec![None; width * height]);
+    let mut came_from: Field<Option<Coord>> = Field::new(width, height, vec![None; width * height]);
+
+    check_ends(&scores, start, goal, &cost_at)?;
+
+    let heuristic = |coord: &Coord| {
+        let distance = match topology {
+            Topology::Square => distance(*coord, goal, diagonal),
+            Topology::Hex => hex_distance(*coord, goal),
+        };
+        (distance as f32 * weight) as i32
+    };
+    let passable = |c: &Coord| cost_at(c).and_then(Cost::passable).is_some();
+
+    let mut open = BinaryHeap::new();
+    scores[&start] = Some(0);
+    open.push(Reverse((heuristic(&start), start)));
+
+    while let Some(Reverse((estimate, coord))) = open.pop() {
+        if coord == goal {
+            return Ok(reconstruct_path(&came_from, goal));
+        }
+
+        let score = scores[&coord].unwrap_or(i32::MAX);
+
+        // Skip stale entries, the cell has been reached cheaper since it was queued.
+        if estimate > score + heuristic(&coord) {
+            continue;
+        }
+
+        for neighbor in topology.neighbors(&coord, width, height) {
+            let neighbor_cost = match cost_at(&neighbor).and_then(Cost::passable) {
+                Some(cost) => cost,
+                None => continue,
+            };
+
+            // Diagonal movement rules only apply to square grids, every hex step is allowed.
+            let dir = neighbor - coord;
+            let step = match topology {
+                Topology::Square if !diagonal.allows(&coord, dir, passable) => continue,
+                Topology::Square => step_cost(dir, neighbor_cost),
+                Topology::Hex => STRAIGHT_COST * (1 + neighbor_cost as i32),
+            };
+
+            let score = score + step;
+            if score < scores[&neighbor].unwrap_or(i32::MAX) {
+                scores[&neighbor] = Some(score);
+                came_from[&neighbor] = Some(coord);
+                open.push(Reverse((score + heuristic(&neighbor), neighbor)));
+            }
+        }
+    }
+
+    Err(PathError::Unreachable)
+}
+
+/// Checks that both ends of a path query are within bounds & passable.
+pub(crate) fn check_ends<T: Default>(
+    field: &Field<T>,
+    start: Coord,
+    goal: Coord,
+    cost_at: impl Fn(&Coord) -> Option<Cost>,
+) -> Result<(), PathError> {
+    if !field.within_bounds(&start) {
+        return Err(PathError::StartOutOfBounds);
+    }
+    if !field.within_bounds(&goal) {
+        return Err(PathError::GoalOutOfBounds);
+    }
+    if cost_at(&start).and_then(Cost::passable).is_none() {
+        return Err(PathError::StartBlocked);
+    }
+    if cost_at(&goal).and_then(Cost::passable).is_none() {
+        return Err(PathError::GoalBlocked);
+    }
+    Ok(())
+}
+
+/// Walks `came_from` back from the goal & returns the path from the start to the goal.
+pub(crate) fn reconstruct_path(came_from: &Field<Option<Coord>>, goal: Coord) -> Vec<Coord> {
+    let mut path = vec![goal];
+    let mut coord = goal;
+    while let Some(previous) = came_from[&coord] {
+        path.push(previous);
+        coord = previous;
+    }
+    path.reverse();
+    path
+}
+
+/// Returns the cheapest possible integration cost between two cells, an admissible A* heuristic.
+pub fn distance(from: Coord, to: Coord, diagonal: DiagonalMovement) -> i32 {
+    let (dx, dy) = ((to.x - from.x).abs(), (to.y - from.y).abs());
+    match diagonal {
+        DiagonalMovement::Never => STRAIGHT_COST * (dx + dy),
+        _ => DIAGONAL_COST * dx.min(dy) + STRAIGHT_COST * (dx - dy).abs(),
+    }
+}
+
+/// Returns the cheapest possible integration cost between two cells of a hex grid, stored at
+/// their offset coordinates.
+pub fn hex_distance(from: Coord, to: Coord) -> i32 {
+    STRAIGHT_COST * Hex::from_offset(from).distance(Hex::from_offset(to)) as i32
+}
+
+/// Smooths a path by string-pulling, skipping every waypoint that has line of sight to a later
+/// one. Shortcuts never cross cells costlier than the waypoints they replace.
+pub fn smooth_path(path: &[Coord], cost_at: impl Fn(&Coord) -> Option<Cost>) -> Vec<Coord> {
+    if path.is_empty() {
+        return vec![];
+    }
+
+    let mut anchor = 0;
+    let mut smoothed = vec![path[anchor]];
+
+    while anchor + 1 < path.len() {
+        let mut next = anchor + 1;
+        let mut max_cost = cost_at(&path[next]).and_then(Cost::passable).unwrap_or(0);
+
+        for candidate in anchor + 2..path.len() {
+            max_cost = max_cost.max(
+                cost_at(&path[candidate])
+                    .and_then(Cost::passable)
+                    .unwrap_or(0),
+            );
+            let visible = line_of_sight(path[anchor], path[candidate], |coord| {
+                cost_at(coord)
+                    .and_then(Cost::passable)
+                    .is_some_and(|cost| cost <= max_cost)
+            });
+            if visible {
+                next = candidate;
+            }
+        }
+
+        smoothed.push(path[next]);
+        anchor = next;
+    }
+
+    smoothed
+}
+
+/// Returns true if every cell a straight line between the centers of two cells passes through is
+/// passable. Lines passing exactly through a corner need both cells next to the corner.
+pub fn line_of_sight(from: Coord, to: Coord, passable: impl Fn(&Coord) -> bool) -> bool {
+    let (dx, dy) = ((to.x - from.x).abs(), (to.y - from.y).abs());
+    let step = Coord::new((to.x - from.x).signum(), (to.y - from.y).signum());
+
+    let mut coord = from;
+    if !passable(&coord) {
+        return false;
+    }
+
+    let (mut ix, mut iy) = (0, 0);
+    while ix < dx || iy < dy {
+        let decision = (1 + 2 * ix) * dy - (1 + 2 * iy) * dx;
+        if decision == 0 {
+            if !passable(&(coord + Coord::new(step.x, 0)))
+                || !passable(&(coord + Coord::new(0, step.y)))
+            {
+                return false;
+            }
+            coord = coord + step;
+            ix += 1;
+            iy += 1;
+        } else if decision < 0 {
+            coord = coord + Coord::new(step.x, 0);
+            ix += 1;
+        } else {
+            coord = coord + Coord::new(0, step.y);
+            iy += 1;
+        }
+
+        if !passable(&coord) {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Requests a path on a grid, answered by a [PathComputed] event for the same requester.
+#[derive(Debug, Clone)]
+pub struct RequestPath {
+    pub query: PathQuery,
+    pub grid_entity: Entity,
+    /// The entity the path is for, e.g. the unit that will follow it.
+    pub requester: Entity,
+}
+
+/// The result of a [RequestPath].
+#[derive(Debug, Clone)]
+pub struct PathComputed {
+    pub query: PathQuery,
+    pub grid_entity: Entity,
+    pub requester: Entity,
+    pub result: Result<Vec<Coord>, PathError>,
+}
+
+/// Consumes [RequestPath] events & answers each with a [PathComputed] event.
+///
+/// The cost shared by the passable cells of each grid is kept for Jump Point Search between
+/// queries. It's kept while changed cells are blocked or get that same cost, other changes
+/// drop it & the grid is scanned again by the next query.
+fn compute_paths(
+    mut ev_request: EventReader<RequestPath>,
+    mut ev_computed: EventWriter<PathComputed>,
+    mut uniform_costs: Local<HashMap<Entity, Option<u8>>>,
+    grids: Query<(&Grid, Option<&CellData>)>,
+    changed_grids: Query<Entity, Changed<Grid>>,
+    changed_cells: ChangedCells,
+    costs: Query<&Cost>,
+) {
+    for entity in changed_grids.iter() {
+        uniform_costs.remove(&entity);
+    }
+    for (entity, changed) in changed_cells.by_grid() {
+        let (uniform, (grid, cells)) = match (uniform_costs.get(&entity), grids.get(entity)) {
+            (Some(uniform), Ok(grid)) => (*uniform, grid),
+            _ => continue,
+        };
+        let kept = uniform.is_some()
+            && changed.iter().all(|coord| {
+                cell_cost(grid, cells, &costs, coord)
+                    .and_then(Cost::passable)
+                    .is_none_or(|cost| Some(cost) == uniform)
+            });
+        if !kept {
+            uniform_costs.remove(&entity);
+        }
+    }
+
+    for ev in ev_request.iter() {
+        let result = match grids.get(ev.grid_entity) {
+            Ok((grid, cells)) => {
+                let query = ev.query.with_topology(grid.topology);
+                let cost_at = |coord: &Coord| cell_cost(grid, cells, &costs, coord);
+                let uniform = match (query.algorithm, query.topology) {
+                    (PathAlgorithm::JumpPoint, Topology::Square) => *uniform_costs
+                        .entry(ev.grid_entity)
+                        .or_insert_with(|| uniform_cost(grid.data.size, cost_at)),
+                    _ => None,
+                };
+                query.find_with_uniform_cost(grid.data.size, uniform, cost_at)
+            }
+            Err(_) => Err(PathError::GridNotFound),
+        };
+
+        if let Err(error) = &result {
+            log::debug!("No path for {:?}: {:?}.", ev.query, error);
+        }
+
+        ev_computed.send(PathComputed {
+            query: ev.query,
+            grid_entity: ev.grid_entity,
+            requester: ev.requester,
+            result,
+        });
+    }
+}
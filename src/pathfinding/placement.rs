@@ -0,0 +1,121 @@
+use crate::prelude::*;
+
+/// The answer to a placement query, see [check_placement].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PlacementCheck {
+    /// Whether placing the footprint cuts any spawn off from any goal.
+    pub blocks_path: bool,
+    /// Integration cost of the longest path from a spawn to its closest goal with the footprint
+    /// placed, `None` if the footprint blocks the path.
+    pub path_cost: Option<i32>,
+    /// The number of steps of that path.
+    pub path_length: Option<usize>,
+}
+
+/// Checks whether blocking the cells of a building footprint would cut any spawn off from any
+/// goal, without changing any live [FlowField].
+pub fn would_block_path(
+    grid: &Grid,
+    cells: Option<&CellData>,
+    costs: &Query<&Cost>,
+    footprint: &[Coord],
+    spawns: &[Coord],
+    goals: &[Goal],
+    diagonal: DiagonalMovement,
+) -> PlacementCheck {
+    check_placement(
+        grid.data.size,
+        footprint,
+        spawns,
+        goals,
+        diagonal,
+        grid.topology,
+        |coord| cell_cost(grid, cells, costs, coord),
+    )
+}
+
+/// Checks whether blocking the `footprint` cells would cut any spawn off from any goal on a grid
+/// of the given size & topology. Spawns & goals outside of the grid count as cut off.
+pub fn check_placement(
+    size: FieldSize,
+    footprint: &[Coord],
+    spawns: &[Coord],
+    goals: &[Goal],
+    diagonal: DiagonalMovement,
+    topology: Topology,
+    cost_at: impl Fn(&Coord) -> Option<Cost>,
+) -> PlacementCheck {
+    let blocked = PlacementCheck {
+        blocks_path: true,
+        ..default()
+    };
+
+    let cost_at = |coord: &Coord| {
+        if footprint.contains(coord) {
+            Some(Cost::Blocked)
+        } else {
+            cost_at(coord)
+        }
+    };
+
+    // Integrate a field per goal, as every spawn has to reach every goal.
+    let mut fields = Vec::with_capacity(goals.len());
+    for goal in goals {
+        let mut field = FlowField::new(size.width, size.height)
+            .with_diagonal(diagonal)
+            .with_topology(topology);
+        field.goals = vec![*goal];
+
+        let mut queue = IntegrationQueue::new();
+        field.seed_goals(&mut queue, cost_at, |_| {});
+        field.integrate(&mut queue, cost_at, |_| {});
+
+        let reaches_spawns = spawns.iter().all(|spawn| {
+            field.integration.within_bounds(spawn) && field.integration[spawn].is_some()
+        });
+        if !reaches_spawns {
+            return blocked;
+        }
+
+        fields.push(field);
+    }
+
+    // Find the spawn with the longest path to its closest goal.
+    let longest = spawns
+        .iter()
+        .filter_map(|spawn| {
+            fields
+                .iter()
+                .enumerate()
+                .filter_map(|(index, field)| field.integration[spawn].map(|cost| (cost, index)))
+                .min()
+                .map(|(cost, index)| (cost, index, *spawn))
+        })
+        .max();
+
+    let (path_cost, index, spawn) = match longest {
+        Some(longest) => longest,
+        None => return PlacementCheck::default(),
+    };
+
+    // Follow the flow from the spawn to count the steps of the path.
+    let field = &mut fields[index];
+    field.update_flow_all();
+    let flow = field.compact().flow;
+
+    let mut coord = spawn;
+    let mut path_length = 0;
+    while let Some(next) = flow[&coord].neighbor(&coord, topology) {
+        if path_length > flow.data.len() {
+            break;
+        }
+        coord = next;
+        path_length += 1;
+    }
+
+    PlacementCheck {
+        blocks_path: false,
+        path_cost: Some(path_cost),
+        path_length: Some(path_length),
+    }
+}
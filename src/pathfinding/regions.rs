@@ -0,0 +1,170 @@
+use crate::prelude::*;
+
+pub struct RegionsPlugin;
+
+impl Plugin for RegionsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set_to_stage(
+            CoreStage::PreUpdate,
+            ConditionSet::new().with_system(update_regions).into(),
+        );
+    }
+}
+
+/// Label of cells that are blocked or outside of the grid, never connected to anything.
+pub const NO_REGION: u32 = 0;
+
+/// Connected regions of the passable cells of a [Grid]. Lives on the grid entity and is kept up
+/// to date as cell costs change, so reachability between two cells is a constant-time lookup.
+#[derive(Component, Debug, Default, Clone)]
+pub struct Regions {
+    /// Which diagonal steps connect cells. Diagonal steps without corner cutting never connect
+    /// cells that aren't connected orthogonally already.
+    pub diagonal: DiagonalMovement,
+    /// The topology of the grid, set from the grid when the regions are built.
+    pub topology: Topology,
+    /// The region label of every cell, [NO_REGION] for blocked cells.
+    pub labels: Field<u32>,
+    next_label: u32,
+}
+
+impl Regions {
+    /// Creates new, unbuilt regions for the given diagonal movement.
+    pub fn new(diagonal: DiagonalMovement) -> Self {
+        Self {
+            diagonal,
+            ..default()
+        }
+    }
+
+    /// Returns true if the regions have been built for a grid of the given size.
+    pub fn is_built_for(&self, size: &FieldSize) -> bool {
+        self.next_label > NO_REGION && self.labels.size == *size
+    }
+
+    /// Returns the region label of a cell, `None` if it's blocked or outside of the grid.
+    pub fn region(&self, coord: &Coord) -> Option<u32> {
+        if !self.labels.within_bounds(coord) || self.labels[coord] == NO_REGION {
+            None
+        } else {
+            Some(self.labels[coord])
+        }
+    }
+
+    /// Returns true if `to` can be reached from `from`.
+    pub fn connected(&self, from: &Coord, to: &Coord) -> bool {
+        match (self.region(from), self.region(to)) {
+            (Some(from), Some(to)) => from == to,
+            _ => false,
+        }
+    }
+
+    /// Labels every passable cell of a grid of the given size.
+    pub fn build(&mut self, size: FieldSize, cost_at: impl Fn(&Coord) -> Option<Cost>) {
+        self.labels = Field::new(
+            size.width,
+            size.height,
+            vec![NO_REGION; size.width * size.height],
+        );
+        self.next_label = NO_REGION + 1;
+
+        for coord in self.labels.iter_coords().collect::<Vec<_>>() {
+            if self.labels[&coord] == NO_REGION {
+                self.flood(coord, &cost_at);
+            }
+        }
+    }
+
+    /// Relabels the regions around the given changed cells. Only the regions touching a changed
+    /// cell are flooded again, merging or splitting them as needed.
+    pub fn update(&mut self, changed: &[Coord], cost_at: impl Fn(&Coord) -> Option<Cost>) {
+        let first_label = self.next_label;
+
+        // Only cells that got blocked or unblocked change the regions.
+        let changed: Vec<Coord> = changed
+            .iter()
+            .copied()
+            .filter(|coord| {
+                self.labels.within_bounds(coord)
+                    && (self.labels[coord] == NO_REGION)
+                        != cost_at(coord).and_then(Cost::passable).is_none()
+            })
+            .collect();
+
+        for coord in changed.iter() {
+            self.labels[coord] = NO_REGION;
+        }
+
+        for coord in changed.iter() {
+            let size = self.labels.size;
+            let seeds: Vec<Coord> = std::iter::once(*coord)
+                .chain(self.topology.neighbors(coord, size.width, size.height))
+                .collect();
+            for seed in seeds {
+                // Skip cells flooded by this update already.
+                if self.labels[&seed] < first_label {
+                    self.flood(seed, &cost_at);
+                }
+            }
+        }
+    }
+
+    /// Labels the region containing `start` with a new label, if it's passable.
+    fn flood(&mut self, start: Coord, cost_at: impl Fn(&Coord) -> Option<Cost>) {
+        let passable = |c: &Coord| cost_at(c).and_then(Cost::passable).is_some();
+        if !passable(&start) {
+            return;
+        }
+
+        let label = self.next_label;
+        self.next_label += 1;
+
+        let (width, height) = (self.labels.size.width, self.labels.size.height);
+        let mut stack = vec![start];
+        self.labels[&start] = label;
+
+        while let Some(coord) = stack.pop() {
+            for neighbor in self.topology.neighbors(&coord, width, height) {
+                if self.labels[&neighbor] == label
+                    || !passable(&neighbor)
+                    || !self.connects(neighbor - coord)
+                {
+                    continue;
+                }
+
+                self.labels[&neighbor] = label;
+                stack.push(neighbor);
+            }
+        }
+    }
+
+    /// Returns true if a step in direction `dir` between two passable cells connects them.
+    /// Cutting corners is the only way a diagonal step adds connectivity, every hex step
+    /// connects.
+    fn connects(&self, dir: Coord) -> bool {
+        self.topology == Topology::Hex
+            || self.diagonal == DiagonalMovement::Always
+            || dir.x == 0
+            || dir.y == 0
+    }
+}
+
+/// Builds the regions of new or resized grids & relabels them where any cell changed.
+fn update_regions(
+    mut grids: Query<(Entity, &Grid, Option<&CellData>, &mut Regions)>,
+    changed: ChangedCells,
+    costs: Query<&Cost>,
+) {
+    let changed_by_grid = changed.by_grid();
+
+    for (entity, grid, cells, mut regions) in grids.iter_mut() {
+        let cost_at = |coord: &Coord| cell_cost(grid, cells, &costs, coord);
+        if !regions.is_built_for(&grid.data.size) || regions.topology != grid.topology {
+            regions.topology = grid.topology;
+            regions.build(grid.data.size, cost_at);
+            log::info!("Regions {:?} built.", entity);
+        } else if let Some(changed) = changed_by_grid.get(&entity) {
+            regions.update(changed, cost_at);
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use std::cmp::Reverse;
+
+use bevy::ecs::system::SystemParam;
+use bevy::utils::HashSet;
+
+use crate::prelude::*;
+
+impl FlowField {
+    /// Locally repairs the integration & flow fields after the cost of the given cells changed.
+    ///
+    /// Every cell whose integration value was derived through a changed cell is invalidated,
+    /// the integration is then resumed from the goals & valid cells bordering the invalidated
+    /// region.
+    /// Only the flow of cells that got a new integration value (and their neighbors) is updated.
+    pub fn repair(&mut self, changed: &[Coord], cost_at: impl Fn(&Coord) -> Option<Cost>) {
+        if self.goals.is_empty() {
+            return;
+        }
+
+        // Invalidate the changed cells & every cell that depends on them.
+        let mut region = Vec::new();
+        let mut stack: Vec<Coord> = changed
+            .iter()
+            .copied()
+            .filter(|coord| self.integration.within_bounds(coord))
+            .collect();
+
+        // Diagonal steps past a changed cell may have become (dis)allowed as well.
+        let square = self.topology == Topology::Square;
+        for coord in changed
+            .iter()
+            .filter(|c| square && self.integration.within_bounds(c))
+        {
+            for from in self.integration.neighbors(coord) {
+                for to in self.integration.neighbors(coord) {
+                    let dir = to - from;
+                    if dir.x == 0 || dir.y == 0 {
+                        continue;
+                    }
+
+                    if let (Some(from_value), Some(to_value), Some(to_cost)) = (
+                        self.integration[&from],
+                        self.integration[&to],
+                        cost_at(&to).and_then(Cost::passable),
+                    ) {
+                        if to_value == from_value + step_cost(dir, to_cost) {
+                            stack.push(to);
+                        }
+                    }
+                }
+            }
+        }
+
+        region.extend(stack.iter().copied());
+
+        let (width, height) = (self.integration.size.width, self.integration.size.height);
+
+        while let Some(coord) = stack.pop() {
+            let value = match self.integration[&coord].take() {
+                Some(value) => value,
+                None => continue,
+            };
+
+            for neighbor in self.topology.neighbors(&coord, width, height) {
+                let neighbor_cost = match cost_at(&neighbor).and_then(Cost::passable) {
+                    Some(cost) => cost,
+                    None => continue,
+                };
+
+                if self.integration[&neighbor]
+                    == Some(value + self.step_cost(neighbor - coord, neighbor_cost))
+                {
+                    stack.push(neighbor);
+                    region.push(neighbor);
+                }
+            }
+        }
+
+        // Resume the integration from the invalidated goals & the valid cells bordering the
+        // invalidated region.
+        let mut queue = IntegrationQueue::new();
+        self.seed_goals(&mut queue, &cost_at, |coord| region.push(coord));
+        for coord in region.iter() {
+            for neighbor in self.topology.neighbors(coord, width, height) {
+                if let Some(value) = self.integration[&neighbor] {
+                    queue.push(Reverse((value, neighbor)));
+                }
+            }
+        }
+
+        let mut touched = region;
+        self.integrate(&mut queue, &cost_at, |coord| touched.push(coord));
+
+        // Update the flow of every touched cell & their neighbors.
+        let mut dirty = touched.clone();
+        for coord in touched.iter() {
+            dirty.extend(self.topology.neighbors(coord, width, height));
+        }
+
+        // Cells may have gained or lost the line of sight to a goal where their integration
+        // changed, or where their line to a goal crosses a changed cell.
+        if self.uses_line_of_sight() && self.visible.size != self.integration.size {
+            self.update_line_of_sight(&cost_at);
+            dirty.extend(self.visible.iter_coords());
+        } else if self.uses_line_of_sight() {
+            let mut stale = touched;
+            for goal in self.goals.iter() {
+                for coord in changed.iter().filter(|c| self.visible.within_bounds(c)) {
+                    stale.extend(self.shadow(goal.coord, *coord));
+                }
+            }
+            stale.sort_unstable();
+            stale.dedup();
+
+            for coord in stale {
+                let visible = self.visible_goal(&coord, &cost_at);
+                if visible != self.visible[&coord] {
+                    self.visible[&coord] = visible;
+                    dirty.push(coord);
+                }
+            }
+        }
+
+        dirty.sort_unstable();
+        dirty.dedup();
+
+        for coord in dirty.iter() {
+            self.update_flow(coord);
+        }
+    }
+
+    /// Returns the cells whose line of sight to `goal` may cross `changed`, the cells of the
+    /// field whose straight line from the goal passes within a cell and a half of it.
+    fn shadow(&self, goal: Coord, changed: Coord) -> Vec<Coord> {
+        let mut shadow = vec![changed];
+        let mut visited = HashSet::from([changed]);
+        let mut stack = vec![changed];
+        while let Some(coord) = stack.pop() {
+            for neighbor in self.visible.neighbors8(&coord) {
+                if visited.insert(neighbor) && crosses(goal, neighbor, changed) {
+                    shadow.push(neighbor);
+                    stack.push(neighbor);
+                }
+            }
+        }
+        shadow
+    }
+}
+
+/// Returns true if the segment between the centers of two cells passes within a cell and a half
+/// of the center of `cell` along both axes.
+fn crosses(from: Coord, to: Coord, cell: Coord) -> bool {
+    let (mut enter, mut exit) = (0.0f32, 1.0f32);
+    for (from, to, cell) in [(from.x, to.x, cell.x), (from.y, to.y, cell.y)] {
+        let (min, max) = (cell as f32 - 1.5, cell as f32 + 1.5);
+        let delta = (to - from) as f32;
+        if delta == 0.0 {
+            if (from as f32) < min || (from as f32) > max {
+                return false;
+            }
+            continue;
+        }
+        let (a, b) = ((min - from as f32) / delta, (max - from as f32) / delta);
+        enter = enter.max(a.min(b));
+        exit = exit.min(a.max(b));
+    }
+    enter <= exit
+}
+
+/// What changed for flow fields since the last frame: the [Cost] or [Terrain] of any cell of
+/// their grid, the [ThreatField] for flow fields weighting threat, the [Congestion], the
+/// [Clearance] for flow fields with a minimum clearance, or the [TerrainCosts] for flow fields of
+/// a movement class. Flow fields are repaired & their caches invalidated for these changes.
+#[derive(SystemParam)]
+pub struct FlowFieldChanges<'w, 's> {
+    grids: FlowFieldGrids<'w, 's>,
+    cells: ChangedCells<'w, 's>,
+    threats: Query<'w, 's, &'static ThreatField, Changed<ThreatField>>,
+    congestions: Query<'w, 's, &'static Congestion, Changed<Congestion>>,
+    clearances: Query<'w, 's, &'static Clearance, Changed<Clearance>>,
+}
+
+impl<'w, 's> FlowFieldChanges<'w, 's> {
+    /// Returns the cells that changed for the flow field on `entity`, every cell of its grid when
+    /// the [TerrainCosts] changed for its movement class.
+    pub fn cells(&self, entity: Entity, flowfield: &FlowField) -> Vec<Coord> {
+        // Any mutable access marks cells & layers as changed, so they're only touched when their
+        // values changed.
+        let grid_entity = self.grids.grid_entity(entity);
+        if flowfield.movement.is_some() && self.grids.terrain_costs.is_changed() {
+            if let Some((grid, _)) = self.grids.get(entity) {
+                return grid.data.iter_coords().collect();
+            }
+        }
+
+        let mut changed = self.cells.of_grid(grid_entity);
+        if flowfield.threat_weight > 0.0 {
+            if let Ok(threat) = self.threats.get(grid_entity) {
+                changed.extend(threat.changed.iter().copied());
+            }
+        }
+        if let Ok(congestion) = self.congestions.get(grid_entity) {
+            changed.extend(congestion.changed.iter().copied());
+        }
+        if flowfield.min_clearance > 1 {
+            if let Ok(clearance) = self.clearances.get(entity) {
+                changed.extend(clearance.changed.iter().copied());
+            }
+        }
+        changed
+    }
+}
+
+/// Repairs the flow fields of grids for every change, see [FlowFieldChanges].
+pub fn repair_flowfield(
+    mut flowfields: Query<(Entity, &mut FlowField)>,
+    mut running: Query<(Option<&mut FlowFieldTask>, Option<&mut FlowFieldProgress>)>,
+    grids: FlowFieldGrids,
+    changes: FlowFieldChanges,
+) {
+    for (entity, mut flowfield) in flowfields.iter_mut() {
+        let changed = changes.cells(entity, &flowfield);
+        if changed.is_empty() {
+            continue;
+        }
+
+        // The snapshot of a running task is outdated for these cells, repair them once it's done.
+        if let Ok((task, progress)) = running.get_mut(entity) {
+            if let Some(mut task) = task {
+                task.changed.extend(changed.iter().copied());
+            }
+            if let Some(mut progress) = progress {
+                progress.changed.extend(changed.iter().copied());
+            }
+        }
+
+        if flowfield.goals.is_empty() {
+            continue;
+        }
+
+        log::debug!(
+            "Repair flowfield {:?} for {} changed cell(s).",
+            entity,
+            changed.len()
+        );
+
+        if let Some((_, cost_at)) = grids.cost_at(entity, &flowfield) {
+            flowfield.repair(&changed, cost_at);
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use crate::prelude::*;
+
+impl FlowField {
+    /// Samples the flow at a world position, see [FlowField::sample_local]. Hex grids aren't
+    /// interpolated, the flow of the hex containing the position is returned.
+    pub fn sample(
+        &self,
+        world_pos: &Vec3,
+        grid: &Grid,
+        grid_transform: &Transform,
+    ) -> Option<Vec2> {
+        sample(|coord| self.get(coord), world_pos, grid, grid_transform)
+    }
+
+    /// Samples the flow at a local position, interpolated bilinearly from the centers of the four
+    /// surrounding cells. Cells without flow, such as blocked or unreachable cells, are left out
+    /// and the weights of the others are renormalized. Returns `None` if none of them has flow.
+    pub fn sample_local(&self, local_pos: &Vec3, cell_size: f32) -> Option<Vec2> {
+        sample_local(|coord| self.get(coord), local_pos, cell_size)
+    }
+}
+
+impl CompactFlowField {
+    /// Samples the flow at a world position, the same as [FlowField::sample].
+    pub fn sample(
+        &self,
+        world_pos: &Vec3,
+        grid: &Grid,
+        grid_transform: &Transform,
+    ) -> Option<Vec2> {
+        sample(|coord| self.get(coord), world_pos, grid, grid_transform)
+    }
+
+    /// Samples the flow at a local position, the same as [FlowField::sample_local].
+    pub fn sample_local(&self, local_pos: &Vec3, cell_size: f32) -> Option<Vec2> {
+        sample_local(|coord| self.get(coord), local_pos, cell_size)
+    }
+}
+
+fn sample(
+    get: impl Fn(&Coord) -> Option<Vec2>,
+    world_pos: &Vec3,
+    grid: &Grid,
+    grid_transform: &Transform,
+) -> Option<Vec2> {
+    let local_pos = grid_transform.compute_matrix().inverse() * world_pos.extend(1.0);
+    match grid.topology {
+        Topology::Square => sample_local(get, &local_pos.xyz(), grid.cell_size),
+        Topology::Hex => get(&grid.local_to_coord(&local_pos.xyz())),
+    }
+}
+
+fn sample_local(
+    get: impl Fn(&Coord) -> Option<Vec2>,
+    local_pos: &Vec3,
+    cell_size: f32,
+) -> Option<Vec2> {
+    let pos = local_pos.pos_2d() / cell_size;
+    let base = pos.floor();
+    let t = pos - base;
+    let origin = Coord::new(base.x as i32, base.y as i32);
+
+    let mut sum = Vec2::ZERO;
+    let mut total = 0.0;
+    for (offset, weight) in [
+        (Coord::new(0, 0), (1.0 - t.x) * (1.0 - t.y)),
+        (Coord::new(1, 0), t.x * (1.0 - t.y)),
+        (Coord::new(0, 1), (1.0 - t.x) * t.y),
+        (Coord::new(1, 1), t.x * t.y),
+    ] {
+        if let Some(flow) = get(&(origin + offset)) {
+            sum += flow * weight;
+            total += weight;
+        }
+    }
+
+    if total > f32::EPSILON {
+        Some(sum / total)
+    } else {
+        None
+    }
+}
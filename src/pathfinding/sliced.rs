@@ -0,0 +1,188 @@
+use std::time::{Duration, Instant};
+
+use crate::prelude::*;
+
+/// Per-frame budget shared by every time-sliced flow field computation & sector graph build,
+/// see [FlowFieldBudgetLeft].
+#[derive(Resource, Debug, Clone)]
+pub struct FlowFieldBudget {
+    /// The maximum number of cells expanded per frame.
+    pub max_nodes: Option<usize>,
+    /// The maximum time spent integrating per frame.
+    pub max_time: Option<Duration>,
+}
+
+impl Default for FlowFieldBudget {
+    fn default() -> Self {
+        Self {
+            max_nodes: Some(20_000),
+            max_time: Some(Duration::from_millis(2)),
+        }
+    }
+}
+
+/// What is left of the [FlowFieldBudget] this frame. Reset at the start of every frame, sector
+/// graph builds spend it first & time-sliced flow fields get the rest.
+#[derive(Resource, Debug, Clone)]
+pub struct FlowFieldBudgetLeft {
+    /// The number of cells that may still be expanded this frame.
+    pub nodes: usize,
+    /// The time that may still be spent this frame.
+    pub time: Option<Duration>,
+}
+
+impl Default for FlowFieldBudgetLeft {
+    /// An unlimited budget.
+    fn default() -> Self {
+        Self {
+            nodes: usize::MAX,
+            time: None,
+        }
+    }
+}
+
+impl FlowFieldBudgetLeft {
+    /// Takes the given number of cells & time from the budget.
+    pub fn spend(&mut self, nodes: usize, time: Duration) {
+        self.nodes = self.nodes.saturating_sub(nodes);
+        self.time = self.time.map(|left| left.saturating_sub(time));
+    }
+
+    /// Returns true once the budget is spent.
+    pub fn is_spent(&self) -> bool {
+        self.nodes == 0 || self.time.is_some_and(|left| left.is_zero())
+    }
+}
+
+/// Refills the [FlowFieldBudgetLeft] from the [FlowFieldBudget] at the start of every frame.
+pub(super) fn reset_flowfield_budget(
+    budget: Res<FlowFieldBudget>,
+    mut left: ResMut<FlowFieldBudgetLeft>,
+) {
+    *left = FlowFieldBudgetLeft {
+        nodes: budget.max_nodes.unwrap_or(usize::MAX),
+        time: budget.max_time,
+    };
+}
+
+/// A flow field being computed over several frames for the grid it's attached to.
+/// The integration queue & in-progress field persist across frames, the current flow field is
+/// kept until the new one is swapped in.
+#[derive(Component, Debug)]
+pub struct FlowFieldProgress {
+    flowfield: FlowField,
+    queue: IntegrationQueue,
+    costs: Field<Option<Cost>>,
+    nodes_expanded: usize,
+    started: Instant,
+    /// Cells whose cost changed since the snapshot was taken, repaired once the field is done.
+    pub changed: Vec<Coord>,
+}
+
+impl FlowFieldProgress {
+    /// Starts the computation of the given flow field against a snapshot of costs.
+    pub fn new(mut flowfield: FlowField, costs: Field<Option<Cost>>) -> Self {
+        flowfield.clear();
+
+        let mut queue = IntegrationQueue::new();
+        flowfield.seed_goals(&mut queue, |coord| snapshot_cost(&costs, coord), |_| {});
+
+        Self {
+            flowfield,
+            queue,
+            costs,
+            nodes_expanded: 0,
+            started: Instant::now(),
+            changed: vec![],
+        }
+    }
+
+    /// Returns the goals of the flow field being computed.
+    pub fn goals(&self) -> &[Goal] {
+        &self.flowfield.goals
+    }
+
+    /// Pops up to `max_nodes` coords from the queue, returns the number of coords popped.
+    pub fn step(&mut self, max_nodes: usize) -> usize {
+        let costs = &self.costs;
+        let mut popped = 0;
+        while popped < max_nodes {
+            match self.flowfield.integrate_next(
+                &mut self.queue,
+                |coord| snapshot_cost(costs, coord),
+                |_| {},
+            ) {
+                Some(expanded) => self.nodes_expanded += expanded as usize,
+                None => break,
+            }
+            popped += 1;
+        }
+        popped
+    }
+
+    /// Returns true if the integration is done.
+    pub fn is_finished(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+fn snapshot_cost(costs: &Field<Option<Cost>>, coord: &Coord) -> Option<Cost> {
+    if costs.within_bounds(coord) {
+        costs[coord]
+    } else {
+        None
+    }
+}
+
+/// Advances time-sliced flow fields within what is left of the [FlowFieldBudget], swapping in
+/// finished ones.
+pub fn step_flowfield_progress(
+    mut commands: Commands,
+    mut budget: ResMut<FlowFieldBudgetLeft>,
+    mut flowfields: Query<(Entity, &mut FlowField, &mut FlowFieldProgress)>,
+    grids: FlowFieldGrids,
+    mut ev_computed: EventWriter<FlowFieldComputed>,
+) {
+    let start = Instant::now();
+    let mut nodes_left = budget.nodes;
+
+    // Expand in small batches so the time budget is checked regularly.
+    const BATCH: usize = 256;
+
+    'flowfields: for (entity, mut flowfield, mut progress) in flowfields.iter_mut() {
+        while !progress.is_finished() && nodes_left > 0 {
+            if budget.time.is_some_and(|max| start.elapsed() >= max) {
+                break 'flowfields;
+            }
+            nodes_left -= progress.step(BATCH.min(nodes_left));
+        }
+
+        if !progress.is_finished() {
+            break;
+        }
+
+        let mut result = std::mem::take(&mut progress.flowfield);
+        result.finish(|coord| snapshot_cost(&progress.costs, coord));
+        let stats = result.stats(
+            progress.nodes_expanded,
+            progress.started.elapsed(),
+            |coord| snapshot_cost(&progress.costs, coord),
+        );
+        *flowfield = result;
+        if let Some((_, cost_at)) = grids.cost_at(entity, &flowfield) {
+            flowfield.repair(&progress.changed, cost_at);
+        }
+
+        log::info!("Flowfield {:?} computed over several frames.", entity);
+
+        commands.entity(entity).remove::<FlowFieldProgress>();
+        ev_computed.send(FlowFieldComputed {
+            grid_entity: entity,
+            goals: flowfield.goals.clone(),
+            stats,
+        });
+    }
+
+    let nodes = budget.nodes - nodes_left;
+    budget.spend(nodes, start.elapsed());
+}
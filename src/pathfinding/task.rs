@@ -0,0 +1,63 @@
+use bevy::tasks::Task;
+use futures_lite::future;
+
+use crate::prelude::*;
+
+/// A flow field being computed on the [AsyncComputeTaskPool] for the grid it's attached to.
+/// Dropping or replacing the component cancels the computation.
+#[derive(Component, Debug)]
+pub struct FlowFieldTask {
+    task: Task<(FlowField, FlowFieldStats)>,
+    /// Cells whose cost changed since the snapshot was taken, repaired once the task finishes.
+    pub changed: Vec<Coord>,
+}
+
+impl FlowFieldTask {
+    /// Spawns the computation of the given flow field against a snapshot of costs.
+    pub fn spawn(mut flowfield: FlowField, costs: Field<Option<Cost>>) -> Self {
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            let stats = flowfield.compute(|coord| {
+                if costs.within_bounds(coord) {
+                    costs[coord]
+                } else {
+                    None
+                }
+            });
+            (flowfield, stats)
+        });
+
+        Self {
+            task,
+            changed: vec![],
+        }
+    }
+}
+
+/// Swaps in the flow fields of finished tasks, repairing the cells that changed meanwhile.
+pub fn poll_flowfield_tasks(
+    mut commands: Commands,
+    mut flowfields: Query<(Entity, &mut FlowField, &mut FlowFieldTask)>,
+    grids: FlowFieldGrids,
+    mut ev_computed: EventWriter<FlowFieldComputed>,
+) {
+    for (entity, mut flowfield, mut task) in flowfields.iter_mut() {
+        let (result, stats) = match future::block_on(future::poll_once(&mut task.task)) {
+            Some(result) => result,
+            None => continue,
+        };
+
+        *flowfield = result;
+        if let Some((_, cost_at)) = grids.cost_at(entity, &flowfield) {
+            flowfield.repair(&task.changed, cost_at);
+        }
+
+        log::info!("Flowfield {:?} computed in the background.", entity);
+
+        commands.entity(entity).remove::<FlowFieldTask>();
+        ev_computed.send(FlowFieldComputed {
+            grid_entity: entity,
+            goals: flowfield.goals.clone(),
+            stats,
+        });
+    }
+}
@@ -0,0 +1,141 @@
+use crate::prelude::*;
+
+pub struct ThreatPlugin;
+
+impl Plugin for ThreatPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set_to_stage(
+            CoreStage::PreUpdate,
+            ConditionSet::new()
+                .with_system(
+                    update_threat_fields
+                        .label(SystemLabels::FlowFieldLayers)
+                        .before(compute_flowfield)
+                        .before(repair_flowfield),
+                )
+                .into(),
+        );
+    }
+}
+
+/// Threatens every cell within `range` of the entity, e.g. the coverage of a tower.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct ThreatSource {
+    pub range: f32,
+    pub threat: u8,
+}
+
+/// The threat of every cell of the grid it's attached to, projected from every [ThreatSource].
+/// Flow fields blend it into the cost of the cells with their `threat_weight`.
+#[derive(Component, Debug, Default, Clone)]
+pub struct ThreatField {
+    pub threat: Field<u8>,
+    /// The cells whose threat changed with the last update.
+    pub changed: Vec<Coord>,
+}
+
+impl ThreatField {
+    /// Returns the threat of a cell, 0 outside of the grid.
+    pub fn get(&self, coord: &Coord) -> u8 {
+        if self.threat.within_bounds(coord) {
+            self.threat[coord]
+        } else {
+            0
+        }
+    }
+
+    /// Projects the given sources onto a grid, returns the threat of every cell.
+    pub fn project<'a>(
+        grid: &Grid,
+        grid_transform: &Transform,
+        sources: impl Iterator<Item = (&'a ThreatSource, Vec3)>,
+    ) -> Field<u8> {
+        let size = grid.data.size;
+        let mut threat = Field::new(
+            size.width,
+            size.height,
+            vec![0_u8; size.width * size.height],
+        );
+
+        for (source, position) in sources {
+            let center = grid.world_to_coord(&position, grid_transform);
+            // Hex rows are closer together than their cells, scan enough rows to cover the range.
+            let spacing = match grid.topology {
+                Topology::Square => grid.cell_size,
+                Topology::Hex => grid.cell_size * 3_f32.sqrt() / 2.0,
+            };
+            let radius = (source.range / spacing).ceil() as i32;
+
+            for y in center.y - radius..=center.y + radius {
+                for x in center.x - radius..=center.x + radius {
+                    let coord = Coord::new(x, y);
+                    if !threat.within_bounds(&coord) {
+                        continue;
+                    }
+
+                    let cell = grid.coord_to_world(&coord, grid_transform);
+                    if (cell - position).pos_2d().length() <= source.range {
+                        threat[&coord] = threat[&coord].saturating_add(source.threat);
+                    }
+                }
+            }
+        }
+
+        threat
+    }
+}
+
+/// Returns the cost of a cell with its threat blended in with the given weight, wrapping another
+/// cost lookup. Blocked cells stay blocked.
+pub fn with_threat<'a>(
+    cost_at: impl Fn(&Coord) -> Option<Cost> + 'a,
+    threat: Option<&'a ThreatField>,
+    weight: f32,
+) -> impl Fn(&Coord) -> Option<Cost> + 'a {
+    move |coord| {
+        let cost = cost_at(coord)?;
+        match (cost, threat) {
+            (Cost::Passable(cost), Some(threat)) if weight > 0.0 => {
+                let extra = (threat.get(coord) as f32 * weight)
+                    .round()
+                    .min(u8::MAX as f32);
+                Some(Cost::Passable(cost.saturating_add(extra as u8)))
+            }
+            _ => Some(cost),
+        }
+    }
+}
+
+/// Projects the threat sources onto every grid with a [ThreatField] whenever a source is added,
+/// moved or removed, & onto resized grids.
+fn update_threat_fields(
+    mut grids: Query<(&Grid, &Transform, &mut ThreatField)>,
+    sources: Query<(&ThreatSource, &GlobalTransform)>,
+    changed: Query<(), Changed<ThreatSource>>,
+    moved: Query<(), (With<ThreatSource>, Changed<GlobalTransform>)>,
+    removed: RemovedComponents<ThreatSource>,
+) {
+    let sources_changed =
+        !changed.is_empty() || !moved.is_empty() || removed.iter().next().is_some();
+
+    for (grid, grid_transform, mut field) in grids.iter_mut() {
+        // Resized grids are projected again even when no source changed.
+        if !sources_changed && field.threat.size == grid.data.size {
+            continue;
+        }
+        let sources = sources
+            .iter()
+            .map(|(source, transform)| (source, transform.translation()));
+        let threat = ThreatField::project(grid, grid_transform, sources);
+
+        let changed: Vec<Coord> = threat
+            .iter_coords()
+            .filter(|coord| field.get(coord) != threat[coord])
+            .collect();
+
+        if !changed.is_empty() || field.threat.size != threat.size {
+            field.threat = threat;
+            field.changed = changed;
+        }
+    }
+}
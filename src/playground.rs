@@ -14,6 +14,7 @@ impl Plugin for PlaygroundPlugin {
             ConditionSet::new()
                 .run_in_state(AppState::InGame)
                 .with_system(update_flow_field_goal)
+                .with_system(request_agent_flowfields.after(update_flow_field_goal))
                 .with_system(update_mouse_hover_coord)
                 .with_system(debug_mouse_position)
                 .into(),
@@ -28,6 +29,7 @@ impl Plugin for PlaygroundPlugin {
         app.insert_resource(MousePosition::default());
         app.insert_resource(PaintData::default());
         app.add_plugin(RTreePlugin3D::<Agent> { ..default() });
+        app.add_plugin(CongestionPlugin::<Agent>::default());
     }
 }
 
@@ -98,20 +100,22 @@ pub struct UnitFlowFieldGrid;
 
 #[derive(Resource, Default)]
 pub struct PaintData {
-    pub block: bool,
+    /// The terrain painted, walls by default & water while holding shift.
+    pub terrain: Terrain,
     pub is_painting: bool,
 }
 
 fn update_flow_field_goal(
     mouse_pos: Res<MousePosition>,
     buttons: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
     mut paint_data: ResMut<PaintData>,
-    grid_query: Query<(Entity, &Grid, &Transform), With<UnitFlowFieldGrid>>,
-    mut cells_query: Query<&mut Cost>,
-    mut ev_compute: EventWriter<ComputeFlowField>,
+    grid_query: Query<(&Grid, &Transform), With<UnitFlowFieldGrid>>,
+    mut agents: Query<&mut Agent>,
+    mut cells_query: Query<&mut Terrain>,
 ) {
     if let Some(point) = mouse_pos.0 {
-        let (entity, grid, grid_transform) = grid_query.single();
+        let (grid, grid_transform) = grid_query.single();
         let coord = grid.world_to_coord(&point, &grid_transform);
 
         if !grid.within_bounds(&coord) {
@@ -119,21 +123,31 @@ fn update_flow_field_goal(
         }
 
         if buttons.just_pressed(MouseButton::Left) {
-            ev_compute.send(ComputeFlowField {
-                goal: coord,
-                grid_entity: entity,
-            });
+            // Holding shift adds another goal instead of replacing them.
+            for mut agent in agents.iter_mut() {
+                if !keys.pressed(KeyCode::LShift) {
+                    agent.goals.clear();
+                }
+                agent.goals.push(coord.into());
+            }
         }
 
+        // Walls & water are painted as terrain, so every movement class sees them through its
+        // own table, e.g. flying units ignore them. Painting over the same terrain erases it.
         if buttons.just_pressed(MouseButton::Right) {
             paint_data.is_painting = true;
+            let painted = if keys.pressed(KeyCode::LShift) {
+                Terrain::Water
+            } else {
+                Terrain::Wall
+            };
             let cell_entity = grid.data[&coord];
             if let Some(entity) = cell_entity {
-                let cost = cells_query.get(entity).unwrap();
-                if *cost == Cost::MAX {
-                    paint_data.block = false;
+                let terrain = cells_query.get(entity).unwrap();
+                paint_data.terrain = if *terrain == painted {
+                    Terrain::Ground
                 } else {
-                    paint_data.block = true;
+                    painted
                 };
             }
         }
@@ -141,12 +155,10 @@ fn update_flow_field_goal(
         if buttons.pressed(MouseButton::Right) && paint_data.is_painting {
             let cell_entity = grid.data[&coord];
             if let Some(entity) = cell_entity {
-                let mut cost = cells_query.get_mut(entity).unwrap();
-                cost.0 = *(if paint_data.block {
-                    Cost::MAX
-                } else {
-                    Cost::EMPTY
-                });
+                let mut terrain = cells_query.get_mut(entity).unwrap();
+                if *terrain != paint_data.terrain {
+                    *terrain = paint_data.terrain;
+                }
             }
         }
 
@@ -156,14 +168,42 @@ fn update_flow_field_goal(
     }
 }
 
+/// Computes the flow fields of the goals agents head for, unless they're cached or already
+/// computed. Every movement class heads for the same goals.
+fn request_agent_flowfields(
+    agents: Query<&Agent>,
+    grids: Query<(Entity, &ClassFlowFields), With<UnitFlowFieldGrid>>,
+    flowfields: Query<(&FlowField, Option<&FlowFieldCache>)>,
+    mut ev_compute: EventWriter<ComputeFlowField>,
+) {
+    let goals = match agents.iter().next() {
+        Some(agent) if !agent.goals.is_empty() => &agent.goals,
+        _ => return,
+    };
+
+    for (entity, classes) in grids.iter() {
+        for target in std::iter::once(entity).chain(classes.iter()) {
+            let (flowfield, cache) = match flowfields.get(target) {
+                Ok(found) => found,
+                Err(_) => continue,
+            };
+            if flowfield.goals != *goals && !cache.is_some_and(|cache| cache.contains(goals)) {
+                ev_compute.send(ComputeFlowField {
+                    goals: goals.clone(),
+                    grid_entity: target,
+                });
+            }
+        }
+    }
+}
+
 fn setup_playground(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
-    mut ev_compute: EventWriter<ComputeFlowField>,
 ) {
     let (width, height) = (25, 25);
-    let flowfield = commands
+    let grid = commands
         .spawn_grid(
             width,
             height,
@@ -171,25 +211,57 @@ fn setup_playground(
             &Transform::from_translation(Vec3::new(0.5 / 2., 0.0, 0.5 / 2.)),
             |cell, coord| {
                 cell.insert(Cost::EMPTY)
+                    .insert(Terrain::Ground)
                     .insert(Name::new(format!("Cell {:} {:}", coord.x, coord.y)));
             },
         )
         .insert((
-            FlowField::new(width, height),
+            FlowField::new(width, height)
+                .with_line_of_sight(true)
+                .with_movement(MovementClass::Ground),
+            FlowFieldCache::default(),
+            Congestion::default(),
             UnitFlowFieldGrid,
             Name::new("FlowField"),
             DebugColor(Color::BEIGE),
         ))
         .id();
 
-    log::info!("Flowfield grid spawned {:?}.", flowfield);
+    log::info!("Flowfield grid spawned {:?}.", grid);
 
-    ev_compute.send(ComputeFlowField {
-        goal: Coord::new(1, 1),
-        grid_entity: flowfield,
-    });
+    // The other movement classes get their own flow field over the same grid.
+    let mut classes = ClassFlowFields::default();
+    for class in [MovementClass::Flying, MovementClass::Heavy] {
+        // Heavy units are two cells wide & only fit through wide corridors.
+        let min_clearance = match class {
+            MovementClass::Heavy => Clearance::for_size(2),
+            _ => 0,
+        };
 
-    for i in 0..5 {
+        let flowfield = commands
+            .spawn((
+                FlowField::new(width, height)
+                    .with_line_of_sight(true)
+                    .with_movement(class)
+                    .with_min_clearance(min_clearance),
+                FlowFieldGrid(grid),
+                FlowFieldCache::default(),
+                Clearance::default(),
+                Name::new(format!("FlowField {:?}", class)),
+            ))
+            .id();
+        classes.0.insert(class, flowfield);
+    }
+    commands.entity(grid).insert(classes);
+
+    let movements = [
+        MovementClass::Ground,
+        MovementClass::Ground,
+        MovementClass::Ground,
+        MovementClass::Heavy,
+        MovementClass::Flying,
+    ];
+    for (i, movement) in movements.into_iter().enumerate() {
         let unit = commands
             .spawn((
                 PbrBundle {
@@ -208,7 +280,9 @@ fn setup_playground(
                 Unit::default(),
                 Name::new("Unit"),
                 DebugColor(Color::RED),
-                Agent::new(flowfield, 15.0),
+                Agent::new(grid, 15.0)
+                    .with_movement(movement)
+                    .with_goals(vec![Coord::new(1, 1).into()]),
             ))
             .id();
         log::info!("Unit spawned {:?}.", unit);
@@ -218,7 +292,12 @@ fn setup_playground(
 #[cfg_attr(feature = "dev", derive(bevy_inspector_egui::Inspectable))]
 #[derive(Component, Debug)]
 pub struct Agent {
-    pub flowfield: Entity,
+    /// The grid the agent moves on, it follows the flow field of its movement class.
+    pub grid: Entity,
+    pub movement: MovementClass,
+    /// The goals the agent heads for, the flow field for them is looked up in the
+    /// [FlowFieldCache] of its movement class.
+    pub goals: Vec<Goal>,
     pub max_speed: f32,
     pub acceleration: Vec2,
 }
@@ -226,42 +305,74 @@ pub struct Agent {
 type AgentSpatialTree = RTreeAccess3D<Agent>;
 
 impl Agent {
-    pub fn new(flowfield: Entity, max_speed: f32) -> Self {
+    pub fn new(grid: Entity, max_speed: f32) -> Self {
         Self {
-            flowfield,
+            grid,
+            movement: default(),
+            goals: vec![],
             max_speed,
             acceleration: Vec2::ZERO,
         }
     }
+
+    /// Sets the movement class of the agent.
+    pub fn with_movement(mut self, movement: MovementClass) -> Self {
+        self.movement = movement;
+        self
+    }
+
+    /// Sets the goals the agent heads for.
+    pub fn with_goals(mut self, goals: Vec<Goal>) -> Self {
+        self.goals = goals;
+        self
+    }
 }
 
 fn agent_flocking(
     mut agents: Query<(Entity, &mut Agent, &Transform)>,
-    flowfields: Query<(&FlowField, &Grid, &Transform)>,
+    grids: Query<(&Grid, &Transform, Option<&ClassFlowFields>)>,
+    flowfields: Query<(&FlowField, Option<&FlowFieldCache>)>,
     velocities: Query<&Velocity, With<Agent>>,
     tree: Res<AgentSpatialTree>,
     mut lines: ResMut<DebugLines>,
 ) {
     for (entity, mut agent, transform) in agents.iter_mut() {
-        let (flowfield, grid, grid_transform) = flowfields
-            .get(agent.flowfield)
-            .expect("Flow field grid not found");
-
-        let goal = match flowfield.goal {
-            Some(goal) => goal,
+        let (grid, grid_transform, classes) =
+            grids.get(agent.grid).expect("Flow field grid not found");
+
+        // Follow the flow field of the movement class, the one of the grid itself by default.
+        let flowfield = classes
+            .and_then(|classes| classes.get(agent.movement))
+            .unwrap_or(agent.grid);
+        let (flowfield, cache) = flowfields.get(flowfield).expect("Flow field not found");
+
+        // Follow the cached field of the agent's goals, or the live one if it's for them.
+        let cached = cache.and_then(|cache| cache.flowfield(&agent.goals));
+        let live = flowfield.goals == agent.goals;
+
+        // Head towards the closest goal.
+        let goal_world = match agent
+            .goals
+            .iter()
+            .map(|goal| grid.coord_to_world(&goal.coord, &grid_transform))
+            .min_by(|a, b| {
+                a.distance_squared(transform.translation)
+                    .total_cmp(&b.distance_squared(transform.translation))
+            }) {
+            Some(goal_world) => goal_world,
             None => continue,
         };
 
-        let goal_world = grid.coord_to_world(&goal, &grid_transform);
-
         if transform.translation.distance(goal_world) < (grid.cell_size / 2.) {
             continue;
         }
 
-        let coord = grid.world_to_coord(&transform.translation, &grid_transform);
-        let flow = flowfield
-            .get(&coord)
-            .unwrap_or((transform.translation - goal_world).pos_2d().normalize());
+        let flow = match &cached {
+            Some(cached) => cached.sample(&transform.translation, grid, grid_transform),
+            None if live => flowfield.sample(&transform.translation, grid, grid_transform),
+            None => None,
+        };
+        let flow = flow.unwrap_or((transform.translation - goal_world).pos_2d().normalize());
 
         let force = flow * 1.0;
 
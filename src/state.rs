@@ -11,4 +11,6 @@ pub enum SystemLabels {
     Input,
     Boids,
     AgentSteering,
+    /// The layers flow fields are computed over: clearance, threat & congestion.
+    FlowFieldLayers,
 }